@@ -0,0 +1,332 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client for the adb host protocol spoken by the adb server on port 5037.
+//! See `SERVICES.TXT` and `protocol.txt` in the adb sources.
+
+use failure::{format_err, Error};
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+const DEFAULT_PORT: u16 = 5037;
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A device as reported by `host:devices-l`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Device {
+    pub serial: String,
+    pub state: String,
+    pub product: Option<String>,
+    pub model: Option<String>,
+    pub device: Option<String>,
+    pub transport_id: Option<String>,
+}
+
+impl Device {
+    /// True if the device is online and can be used
+    pub fn is_online(&self) -> bool {
+        self.state == "device"
+    }
+}
+
+/// Parse the payload of `host:devices-l` and `host:track-devices-l`
+pub fn parse_devices(payload: &str) -> Vec<Device> {
+    payload
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let serial = fields.next()?.to_owned();
+            let state = fields.next()?.to_owned();
+            let mut device = Device {
+                serial,
+                state,
+                ..Default::default()
+            };
+            for field in fields {
+                match field.split_once(':') {
+                    Some(("product", v)) => device.product = Some(v.to_owned()),
+                    Some(("model", v)) => device.model = Some(v.to_owned()),
+                    Some(("device", v)) => device.device = Some(v.to_owned()),
+                    Some(("transport_id", v)) => device.transport_id = Some(v.to_owned()),
+                    _ => (),
+                }
+            }
+            Some(device)
+        })
+        .collect()
+}
+
+/// Quote `arg` for the device shell
+pub fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c))
+    {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Blocking adb host protocol client
+#[derive(Clone, Debug)]
+pub struct Client {
+    addr: SocketAddr,
+}
+
+impl Default for Client {
+    /// Server address from `ADB_SERVER_SOCKET` (tcp:host:port) or
+    /// `ANDROID_ADB_SERVER_PORT`. Defaults to localhost:5037
+    fn default() -> Client {
+        let addr = env::var("ADB_SERVER_SOCKET")
+            .ok()
+            .and_then(|s| {
+                s.strip_prefix("tcp:")
+                    .and_then(|a| a.to_socket_addrs().ok())
+                    .and_then(|mut a| a.next())
+            })
+            .unwrap_or_else(|| {
+                let port = env::var("ANDROID_ADB_SERVER_PORT")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(DEFAULT_PORT);
+                SocketAddr::from(([127, 0, 0, 1], port))
+            });
+        Client::new(addr)
+    }
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Client {
+        Client { addr }
+    }
+
+    /// List devices with their properties
+    pub fn devices(&self) -> Result<Vec<Device>, Error> {
+        let mut stream = self.request("host:devices-l")?;
+        let payload = read_hex_string(&mut stream)?;
+        Ok(parse_devices(&payload))
+    }
+
+    /// Track device changes. Every item is the full list of devices.
+    pub fn track_devices(&self) -> Result<TrackDevices, Error> {
+        let stream = self.request("host:track-devices-l")?;
+        Ok(TrackDevices {
+            stream,
+            done: false,
+        })
+    }
+
+    /// Open `service` e.g `shell:ls` or `exec:logcat` on the device selected by
+    /// `serial` or any device and return the raw stream
+    pub fn open(&self, serial: Option<&str>, service: &str) -> Result<TcpStream, Error> {
+        let transport = match serial {
+            Some(serial) => format!("host:transport:{serial}"),
+            None => "host:transport-any".to_owned(),
+        };
+        let mut stream = self.request(&transport)?;
+        send(&mut stream, service)?;
+        read_status(&mut stream)?;
+        Ok(stream)
+    }
+
+    /// Wait until the device selected by `serial` or any device is online
+    pub fn wait_for_device(&self, serial: Option<&str>) -> Result<(), Error> {
+        for devices in self.track_devices()? {
+            if devices?
+                .iter()
+                .any(|d| d.is_online() && serial.map(|s| s == d.serial).unwrap_or(true))
+            {
+                return Ok(());
+            }
+        }
+        Err(format_err!(
+            "adb: connection closed while waiting for device"
+        ))
+    }
+
+    /// Run `command` with the `shell:` service and return the output
+    pub fn shell(&self, serial: Option<&str>, command: &str) -> Result<Vec<u8>, Error> {
+        let mut stream = self.open(serial, &format!("shell:{command}"))?;
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    /// Connect and send a host request
+    fn request(&self, request: &str) -> Result<TcpStream, Error> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, CONNECT_TIMEOUT)
+            .map_err(|e| format_err!("Failed to connect to adb server {}: {}", self.addr, e))?;
+        stream.set_nodelay(true).ok();
+        send(&mut stream, request)?;
+        read_status(&mut stream)?;
+        Ok(stream)
+    }
+}
+
+/// Blocking iterator over device list updates. Ends when the server closes
+/// the connection or after the first error.
+pub struct TrackDevices {
+    stream: TcpStream,
+    done: bool,
+}
+
+impl Iterator for TrackDevices {
+    type Item = Result<Vec<Device>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match read_hex_string(&mut self.stream) {
+            Ok(payload) => Some(Ok(parse_devices(&payload))),
+            Err(e) => {
+                self.done = true;
+                match e.downcast_ref::<io::Error>() {
+                    Some(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                    _ => Some(Err(e)),
+                }
+            }
+        }
+    }
+}
+
+/// Send a request prefixed with its length as four hex digits
+fn send<W: Write>(w: &mut W, request: &str) -> Result<(), Error> {
+    w.write_all(format!("{:04x}{}", request.len(), request).as_bytes())?;
+    Ok(())
+}
+
+/// Read OKAY or FAIL followed by the failure message
+fn read_status<R: Read>(r: &mut R) -> Result<(), Error> {
+    let mut status = [0u8; 4];
+    r.read_exact(&mut status)?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => Err(format_err!("adb: {}", read_hex_string(r)?)),
+        s => Err(format_err!(
+            "adb: invalid status {}",
+            String::from_utf8_lossy(s)
+        )),
+    }
+}
+
+/// Read a string prefixed with its length as four hex digits
+fn read_hex_string<R: Read>(r: &mut R) -> Result<String, Error> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = usize::from_str_radix(&String::from_utf8_lossy(&len), 16)
+        .map_err(|e| format_err!("adb: invalid length: {}", e))?;
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    Ok(String::from_utf8_lossy(&payload).into_owned())
+}
+
+/// Minimal adb server that answers the requests of one connection
+#[cfg(test)]
+fn fake_server(responses: Vec<(&'static str, String)>) -> Client {
+    use std::{net::TcpListener, thread};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        for (request, response) in responses {
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).unwrap();
+            let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap();
+            let mut payload = vec![0u8; len];
+            stream.read_exact(&mut payload).unwrap();
+            if payload == request.as_bytes() {
+                stream.write_all(b"OKAY").unwrap();
+                stream.write_all(response.as_bytes()).unwrap();
+            } else {
+                let msg = "unknown request";
+                write!(stream, "FAIL{:04x}{}", msg.len(), msg).unwrap();
+            }
+        }
+    });
+    Client::new(addr)
+}
+
+#[test]
+fn test_devices() {
+    let payload =
+        "emulator-5554 device product:sdk_gphone model:Pixel_3 device:generic transport_id:1\n\
+                   0123456789 unauthorized transport_id:2\n";
+    let response = format!("{:04x}{}", payload.len(), payload);
+    let client = fake_server(vec![("host:devices-l", response)]);
+    let devices = client.devices().unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].serial, "emulator-5554");
+    assert_eq!(devices[0].state, "device");
+    assert_eq!(devices[0].model.as_deref(), Some("Pixel_3"));
+    assert_eq!(devices[1].state, "unauthorized");
+}
+
+#[test]
+fn test_shell() {
+    let client = fake_server(vec![
+        ("host:transport:emulator-5554", String::new()),
+        ("shell:pidof system_server", "1234\n".to_owned()),
+    ]);
+    let output = client
+        .shell(Some("emulator-5554"), "pidof system_server")
+        .unwrap();
+    assert_eq!(output, b"1234\n");
+}
+
+#[test]
+fn test_wait_for_device() {
+    let updates = ["", "emulator-5554 offline\n", "emulator-5554 device\n"]
+        .iter()
+        .map(|p| format!("{:04x}{}", p.len(), p))
+        .collect::<String>();
+    let client = fake_server(vec![("host:track-devices-l", updates)]);
+    client.wait_for_device(Some("emulator-5554")).unwrap();
+
+    // The connection is closed before the device shows up
+    let updates = format!("{:04x}{}", 0, "");
+    let client = fake_server(vec![("host:track-devices-l", updates.clone())]);
+    assert!(client.wait_for_device(Some("emulator-5554")).is_err());
+
+    let client = fake_server(vec![("host:track-devices-l", updates)]);
+    assert_eq!(client.track_devices().unwrap().count(), 1);
+}
+
+#[test]
+fn test_fail() {
+    let client = fake_server(vec![("host:transport:nope", String::new())]);
+    let e = client.shell(Some("foo"), "true").unwrap_err();
+    assert_eq!(e.to_string(), "adb: unknown request");
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("logcat"), "logcat");
+    assert_eq!(quote("01-02 03:04:05.000"), "'01-02 03:04:05.000'");
+    assert_eq!(quote("it's"), "'it'\\''s'");
+    assert_eq!(quote(""), "''");
+}
@@ -0,0 +1,204 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::{err_msg, format_err, Error};
+use rogcat::parser::{bugreport_section, property};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+use zip::ZipArchive;
+
+/// A `------ NAME (command) ------` block of a bugreport
+#[derive(Debug)]
+pub struct Section {
+    pub name: String,
+    pub command: String,
+    pub lines: Vec<String>,
+}
+
+impl Section {
+    /// True if the section contains logcat output that can be parsed into records
+    pub fn is_logcat(&self) -> bool {
+        self.command.starts_with("logcat") && !self.command.contains(" -S")
+    }
+}
+
+/// A bugreport split into its sections
+#[derive(Debug, Default)]
+pub struct Bugreport {
+    pub sections: Vec<Section>,
+}
+
+impl Bugreport {
+    /// Open a plain text or zipped bugreport
+    pub fn open(path: &Path) -> Result<Bugreport, Error> {
+        let file = File::open(path)
+            .map_err(|e| format_err!("Failed to open {}: {}", path.display(), e))?;
+        if path.extension().map(|e| e == "zip").unwrap_or(false) {
+            let mut zip = ZipArchive::new(file)?;
+            let main = main_entry(&mut zip)?;
+            let entry = zip.by_name(&main)?;
+            Bugreport::from_reader(BufReader::new(entry))
+        } else {
+            Bugreport::from_reader(BufReader::new(file))
+        }
+    }
+
+    /// Split the lines read from `reader` into sections. Lines before the
+    /// first section header end up in a section named "HEADER".
+    pub fn from_reader<R: BufRead>(mut reader: R) -> Result<Bugreport, Error> {
+        let mut sections = vec![Section {
+            name: "HEADER".into(),
+            command: String::new(),
+            lines: Vec::new(),
+        }];
+
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            if reader.read_until(b'\n', &mut buffer)? == 0 {
+                break;
+            }
+            while buffer.ends_with(b"\r") || buffer.ends_with(b"\n") {
+                buffer.pop();
+            }
+            let line = String::from_utf8_lossy(&buffer).into_owned();
+
+            if let Ok((_, (name, command))) = bugreport_section(&line) {
+                sections.push(Section {
+                    name,
+                    command,
+                    lines: Vec::new(),
+                });
+            } else if line.starts_with("------ ") && line.contains(" was the duration of ") {
+                // Section footer
+                continue;
+            } else if let Some(section) = sections.last_mut() {
+                section.lines.push(line);
+            }
+        }
+
+        Ok(Bugreport { sections })
+    }
+
+    /// Find sections by case insensitive name
+    pub fn sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Section> {
+        self.sections
+            .iter()
+            .filter(move |s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// System properties from the `getprop` section
+    pub fn properties(&self) -> BTreeMap<String, String> {
+        self.sections
+            .iter()
+            .filter(|s| s.command == "getprop")
+            .flat_map(|s| s.lines.iter())
+            .filter_map(|l| property(l).ok().map(|(_, p)| p))
+            .collect()
+    }
+}
+
+/// Name of the main bugreport text file within a bugreport zip
+pub fn main_entry<R: Read + std::io::Seek>(zip: &mut ZipArchive<R>) -> Result<String, Error> {
+    if let Ok(mut entry) = zip.by_name("main_entry.txt") {
+        let mut name = String::new();
+        entry.read_to_string(&mut name)?;
+        return Ok(name.trim().to_owned());
+    }
+
+    // Prefer bugreport-*.txt over any other text file in the top level directory
+    let mut candidates = (0..zip.len())
+        .filter_map(|i| zip.by_index(i).ok().map(|e| e.name().to_owned()))
+        .filter(|n| !n.contains('/') && n.ends_with(".txt"))
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|n| !n.starts_with("bugreport"));
+    candidates
+        .into_iter()
+        .next()
+        .ok_or_else(|| err_msg("Failed to find bugreport text in zip"))
+}
+
+#[test]
+fn test_from_reader() {
+    use std::io::Cursor;
+
+    let text = "========================================================
+== dumpstate: 2019-05-01 12:00:00
+========================================================
+
+Build: QQ1A.191205.008
+------ SYSTEM PROPERTIES (getprop) ------
+[ro.build.version.sdk]: [29]
+[ro.product.model]: [Pixel 3]
+------ 0.021s was the duration of 'SYSTEM PROPERTIES' ------
+------ SYSTEM LOG (logcat -v threadtime -v printable -v uid -d *:v) ------
+--------- beginning of main
+05-01 12:00:00.000  1000  1000  1100 I ActivityManager: Start proc\r
+------ 0.300s was the duration of 'SYSTEM LOG' ------
+------ LOG STATISTICS (logcat -b all -S) ------
+size/num main system
+------ EVENT LOG (logcat -b events -v threadtime -v printable -v uid -d *:v) ------
+";
+    let report = Bugreport::from_reader(Cursor::new(text)).unwrap();
+    let names = report
+        .sections
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            "HEADER",
+            "SYSTEM PROPERTIES",
+            "SYSTEM LOG",
+            "LOG STATISTICS",
+            "EVENT LOG"
+        ]
+    );
+    assert_eq!(report.sections[0].lines.len(), 5);
+    assert_eq!(
+        report.sections[2].lines,
+        vec![
+            "--------- beginning of main",
+            "05-01 12:00:00.000  1000  1000  1100 I ActivityManager: Start proc",
+        ]
+    );
+    assert!(report.sections[4].lines.is_empty());
+
+    let logcat = report
+        .sections
+        .iter()
+        .filter(|s| s.is_logcat())
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(logcat, vec!["SYSTEM LOG", "EVENT LOG"]);
+    assert_eq!(report.sections("system log").count(), 1);
+    assert_eq!(
+        report
+            .properties()
+            .get("ro.product.model")
+            .map(String::as_str),
+        Some("Pixel 3")
+    );
+}
@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::utils;
+use crate::{theme::THEMES, utils};
 use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, SubCommand};
 use lazy_static::lazy_static;
 use rogcat::record::Level;
@@ -32,6 +32,121 @@ lazy_static! {
     };
 }
 
+/// Filter options that are shared by the serve subcommand and its clients
+fn filter_args() -> [Arg<'static, 'static>; 4] {
+    [
+        Arg::with_name("level")
+            .short("l")
+            .long("level")
+            .takes_value(true)
+            .possible_values(Level::values())
+            .help("Minimum level"),
+        Arg::with_name("filter")
+            .long("filter")
+            .short("f")
+            .takes_value(true)
+            .multiple(true)
+            .help("Regex filter on tag, pid, thread and message."),
+        Arg::with_name("message")
+            .short("m")
+            .long("message")
+            .takes_value(true)
+            .multiple(true)
+            .help("Message filters in RE2. The prefix '!' inverts the match"),
+        Arg::with_name("tag")
+            .short("t")
+            .long("tag")
+            .takes_value(true)
+            .multiple(true)
+            .help("Tag filters in RE2. The prefix '!' inverts the match"),
+    ]
+}
+
+/// Options that select the logcat or file source of subcommands
+fn source_args() -> [Arg<'static, 'static>; 4] {
+    [
+        Arg::with_name("input")
+            .short("i")
+            .long("input")
+            .takes_value(true)
+            .multiple(true)
+            .help("Read from file(s) instead of adb logcat. Pass \"-\" to read from stdin"),
+        Arg::with_name("dev")
+            .short("s")
+            .long("serial")
+            .takes_value(true)
+            .conflicts_with("input")
+            .help("Forwards the device selector to adb"),
+        Arg::with_name("buffer")
+            .short("b")
+            .long("buffer")
+            .multiple(true)
+            .takes_value(true)
+            .conflicts_with("input")
+            .help("Select specific logd buffers. Defaults to main, events, kernel and crash"),
+        Arg::with_name("dump")
+            .short("d")
+            .long("dump")
+            .conflicts_with("input")
+            .help("Dump the log and then exit (don't block)"),
+    ]
+}
+
+/// Profile selection of subcommands
+fn profile_args() -> [Arg<'static, 'static>; 2] {
+    [
+        Arg::with_name("profiles-path")
+            .short("P")
+            .long("profiles-path")
+            .takes_value(true)
+            .help("Manually specify profile file (overrules ROGCAT_PROFILES)"),
+        Arg::with_name("profile")
+            .short("p")
+            .long("profile")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Select profile. Pass multiple times to merge profiles"),
+    ]
+}
+
+/// Options to replay recorded logs with their original timing
+fn replay_args() -> [Arg<'static, 'static>; 4] {
+    [
+        Arg::with_name("replay")
+            .long("replay")
+            .requires("input")
+            .help("Replay the input paced by the timestamps of the records"),
+        Arg::with_name("replay-speed")
+            .long("replay-speed")
+            .takes_value(true)
+            .value_name("FACTOR")
+            .requires("replay")
+            .help("Replay speed factor e.g 2x or 0.5x"),
+        Arg::with_name("replay-max-gap")
+            .long("replay-max-gap")
+            .takes_value(true)
+            .value_name("DURATION")
+            .requires("replay")
+            .help("Limit the gap between two records e.g 500ms, 5s or 1m"),
+        Arg::with_name("replay-start")
+            .long("replay-start")
+            .takes_value(true)
+            .value_name("DURATION")
+            .requires("replay")
+            .help("Skip DURATION at the start of the input"),
+    ]
+}
+
+/// Parser for filter lines e.g sent by clients of rogcat serve or used in triggers
+pub fn client_filter() -> App<'static, 'static> {
+    App::new("filter")
+        .setting(AppSettings::NoBinaryName)
+        .setting(AppSettings::DisableHelpFlags)
+        .setting(AppSettings::DisableVersion)
+        .args(&filter_args())
+}
+
 /// Build cli
 pub fn cli() -> App<'static, 'static> {
     App::new(crate_name!())
@@ -44,6 +159,17 @@ pub fn cli() -> App<'static, 'static> {
                .long("restart")
                .conflicts_with_all(&["dump", "input", "tail"])
                .help("Restart command on exit"))
+          .arg(Arg::with_name("trigger")
+               .long("trigger")
+               .takes_value(true)
+               .multiple(true)
+               .number_of_values(1)
+               .allow_hyphen_values(true)
+               .help("Run an action when records match a filter. Format is \"FILTER => ACTION\" with the filter options of rogcat and the actions \"exec COMMAND\", \"bugreport [DIR]\", \"marker TEXT\", \"exit [CODE]\" or \"count N [CODE]\" e.g \"-t ActivityManager -m ANR => bugreport\""))
+          .arg(Arg::with_name("exit-code")
+               .long("exit-code")
+               .requires("COMMAND")
+               .help("Exit with the exit code of COMMAND"))
 
           // Logcat specific
           .arg(Arg::with_name("buffer")
@@ -58,7 +184,7 @@ pub fn cli() -> App<'static, 'static> {
                .long("serial")
                .takes_value(true)
                .multiple(false)
-               .help("Forwards the device selector to adb"))
+               .help("Forwards the device selector to adb or the target to ffx"))
           .arg(Arg::with_name("last")
                .short("L")
                .long("last")
@@ -87,22 +213,28 @@ pub fn cli() -> App<'static, 'static> {
           .arg(Arg::with_name("format")
                .long("format")
                .takes_value(true)
-               .possible_values(&["csv", "html", "human", "json", "raw"]).help("Output format. Defaults to human on stdout and raw on file output"))
+               .possible_values(&["csv", "html", "human", "json", "raw", "sqlite"]).help("Output format. Defaults to human on stdout and raw on file output. Files ending in .db default to sqlite"))
 
           // Display options
           .arg(Arg::with_name("color")
                .long("color")
                .takes_value(true)
                .possible_values(&["auto", "always", "never"])
-               .conflicts_with_all(&["highlight", "output"])
+               .conflicts_with_all(&["output"])
                .help("Terminal coloring option"))
           .arg(Arg::with_name("highlight")
                .short("h")
                .long("highlight")
                .takes_value(true)
                .multiple(true)
-               .conflicts_with_all(&["output"])
-               .help( "Highlight messages that match this pattern in RE2. The prefix '!' inverts the match"))
+               .number_of_values(1)
+               .help("Highlight the parts of messages that match a pattern in RE2 with a style e.g \"FATAL => bold red on white\". Styles are combinations of bold, underline, a foreground color and \"on\" a background color. Colors are names, 0-255 or #rrggbb. Defaults to bold yellow"))
+          .arg(Arg::with_name("theme")
+               .long("theme")
+               .takes_value(true)
+               .possible_values(THEMES)
+               .conflicts_with("output")
+               .help("Color theme of the terminal output. Overrides the theme name in the configuration file"))
           .arg(Arg::with_name("no-dimm")
                .long("no-dimm")
                .conflicts_with("output")
@@ -119,6 +251,39 @@ pub fn cli() -> App<'static, 'static> {
                .long("show-date")
                .conflicts_with("output")
                .help("Show month and day in terminal output"))
+          .arg(Arg::with_name("wrap")
+               .long("wrap")
+               .takes_value(true)
+               .possible_values(&["word", "char", "truncate"])
+               .conflicts_with("output")
+               .help("Wrap long messages in terminal output at word boundaries (default) or any character, or truncate them to a single line"))
+          .arg(Arg::with_name("pager")
+               .long("pager")
+               .conflicts_with("output")
+               .help("Page the output with $PAGER or less -R also when following logs"))
+          .arg(Arg::with_name("no-pager")
+               .long("no-pager")
+               .help("Don't page the output of --dump, --tail, --last or --input"))
+          .arg(Arg::with_name("compact")
+               .long("compact")
+               .conflicts_with("output")
+               .help("Omit process and thread and abbreviate tags in terminal output for narrow terminals"))
+          .arg(Arg::with_name("time")
+               .long("time")
+               .takes_value(true)
+               .possible_values(&["absolute", "relative", "delta", "marker"])
+               .help("Show the time of day, the time since the first record, since the previous record or since the last record that matches --time-marker. Relative times are also added to csv and json output"))
+          .arg(Arg::with_name("time-marker")
+               .long("time-marker")
+               .takes_value(true)
+               .value_name("PATTERN")
+               .required_if("time", "marker")
+               .help("Message pattern in RE2 of the records that --time marker is measured from"))
+          .arg(Arg::with_name("time-gap")
+               .long("time-gap")
+               .takes_value(true)
+               .value_name("DURATION")
+               .help("Flag the time of records that follow a gap longer than DURATION e.g 500ms. Defaults to 1s unless the time is absolute"))
 
           // Input, Output
           .arg(Arg::with_name("input")
@@ -127,6 +292,17 @@ pub fn cli() -> App<'static, 'static> {
                .takes_value(true)
                .multiple(true)
                .help( "Read from file instead of command. Use 'serial://COM0@115200,8N1 or similiar for reading a serial port"))
+          .arg(Arg::with_name("fts")
+               .long("fts")
+               .takes_value(true)
+               .requires("input")
+               .help("Full text search in the messages of sqlite input files e.g \"anr OR crash*\""))
+          .arg(Arg::with_name("where")
+               .long("where")
+               .takes_value(true)
+               .requires("input")
+               .help("SQL condition on the records of sqlite input files e.g \"process = '1234'\""))
+          .args(&replay_args())
           .arg(Arg::with_name("output")
                .short("o")
                .long("output")
@@ -153,6 +329,13 @@ pub fn cli() -> App<'static, 'static> {
                          'enumerate' appends a file sequence number after the filename passed with '-o' option whenever a new file is created \
                          (see 'records-per-file' option). 'date' will prefix the output filename with the current local date when a new file is created"))
 
+          // Crashes
+          .arg(Arg::with_name("crash-reports")
+               .long("crash-reports")
+               .takes_value(true)
+               .value_name("DIR")
+               .help("Detect native crashes and ANRs and write a report per crash to DIR"))
+
           // Profiles
           .arg(Arg::with_name("profiles-path")
                .short("P")
@@ -163,7 +346,9 @@ pub fn cli() -> App<'static, 'static> {
                .short("p")
                .long("profile")
                .takes_value(true)
-               .help("Select profile"))
+               .multiple(true)
+               .number_of_values(1)
+               .help("Select profile. Pass multiple times to merge profiles"))
 
 
           // Filter
@@ -222,18 +407,250 @@ pub fn cli() -> App<'static, 'static> {
           .arg(Arg::with_name("fuchsia")
                .long("ffx")
                .takes_value(false)
-               .conflicts_with_all(&["buffer", "dev", "input", "COMMAND", "restart", "tail"])
+               .conflicts_with_all(&["buffer", "input", "COMMAND", "restart", "tail"])
                .help("Use ffx log instead of adb logcat"))
+          .arg(Arg::with_name("since")
+               .long("since")
+               .takes_value(true)
+               .conflicts_with_all(&["input", "COMMAND"])
+               .help("Only show records since the given time. Forwarded as \"--since\" to ffx log"))
 
           // Command
           .arg(Arg::with_name("COMMAND")
-               .help( "Optional command to run and capture stdout and stdderr from. Pass \"-\" to d capture stdin'. If omitted, rogcat will run \"adb logcat -b all\" and restarts this commmand if 'adb' terminates",))
+               .multiple(true)
+               .help( "Optional command to run and capture stdout and stdderr from. Pass \"-\" to d capture stdin'. If omitted, rogcat will run \"adb logcat -b all\" and restarts this commmand if 'adb' terminates. A single COMMAND is split like a shell would do. Use \"-- cmd args...\" to pass the arguments as they are",))
+          .arg(Arg::with_name("remote-filter")
+               .long("remote-filter")
+               .takes_value(true)
+               .value_name("FILTER")
+               .allow_hyphen_values(true)
+               .requires("COMMAND")
+               .help("Filter sent to a rogcat server when COMMAND is a tcp:// or unix:// address e.g \"-t ActivityManager -l warn\""))
+          .arg(Arg::with_name("shell")
+               .long("shell")
+               .requires("COMMAND")
+               .help("Run COMMAND with sh -c"))
           // Bugreport
           .subcommand(SubCommand::with_name("bugreport")
-                    .about("Capture bugreport. This is only works for Android versions < 7.")
-                    .arg(Arg::with_name("zip").short("z").long("zip").help("Zip report"))
+                    .about("Capture bugreport. Android 7+ devices create a zip that is pulled from the device")
+                    .arg(Arg::with_name("zip").short("z").long("zip").help("Zip report. Android < 7 only - newer versions are always zipped"))
                     .arg(Arg::with_name("overwrite").long("overwrite").help("Overwrite report file if present"))
-                    .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport")))
+                    .arg(Arg::with_name("dev")
+                         .short("s")
+                         .long("serial")
+                         .takes_value(true)
+                         .help("Forwards the device selector to adb"))
+                    .arg(Arg::with_name("index")
+                         .long("index")
+                         .help("List the contents of the zip and the sections of the report once pulled. Android 7+ only"))
+                    .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport.zip or <now>-bugreport.txt"))
+                    .subcommand(SubCommand::with_name("inspect")
+                              .about("Inspect the sections of a bugreport (text or zip). Lists the sections unless another mode is selected")
+                              .arg(Arg::with_name("file")
+                                   .required(true)
+                                   .help("Bugreport text or zip file"))
+                              .arg(Arg::with_name("section")
+                                   .short("S")
+                                   .long("section")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .help("Print the sections with this name (case insensitive). Limits --extract and --logcat to these sections"))
+                              .arg(Arg::with_name("extract")
+                                   .short("x")
+                                   .long("extract")
+                                   .takes_value(true)
+                                   .value_name("DIR")
+                                   .conflicts_with_all(&["properties", "logcat"])
+                                   .help("Write each section into a separate file in DIR"))
+                              .arg(Arg::with_name("properties")
+                                   .long("properties")
+                                   .conflicts_with_all(&["section", "logcat"])
+                                   .help("Print the system properties"))
+                              .arg(Arg::with_name("logcat")
+                                   .long("logcat")
+                                   .help("Parse and filter the logcat sections"))
+                              .args(&profile_args().map(|a| a.requires("logcat")))
+                              .args(&filter_args().map(|a| a.requires("logcat")))))
+          // Serve
+          .subcommand(SubCommand::with_name("serve")
+                    .about("Serve the processed records to TCP or Unix socket clients. Clients can send a filter line like \"-t ActivityManager -l warn\"")
+                    .arg(Arg::with_name("listen")
+                         .long("listen")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .value_name("ADDR")
+                         .default_value("tcp://127.0.0.1:5555")
+                         .help("Listen on tcp://host:port or unix:///path"))
+                    .arg(Arg::with_name("format")
+                         .long("format")
+                         .takes_value(true)
+                         .possible_values(&["csv", "json", "raw"])
+                         .default_value("json")
+                         .help("Format of the records sent to clients"))
+                    .arg(Arg::with_name("queue")
+                         .long("queue")
+                         .takes_value(true)
+                         .help("Number of records queued per client before records are dropped"))
+                    .args(&source_args())
+                    .args(&profile_args())
+                    .args(&filter_args())
+                    .args(&replay_args()))
+          // Stats
+          .subcommand(SubCommand::with_name("stats")
+                    .about("Print statistics of records per tag, process, thread, level and time. Live sources print on Ctrl-C")
+                    .args(&source_args())
+                    .args(&profile_args())
+                    .args(&filter_args())
+                    .arg(Arg::with_name("top")
+                         .long("top")
+                         .takes_value(true)
+                         .help("Number of entries in the top lists. Defaults to 10"))
+                    .arg(Arg::with_name("json")
+                         .long("json")
+                         .help("Print the statistics as JSON")))
+          // Convert
+          .subcommand(SubCommand::with_name("convert")
+                    .about("Convert records between formats. Reads stdin and writes stdout if no files are given")
+                    .arg(Arg::with_name("input")
+                         .short("i")
+                         .long("input")
+                         .takes_value(true)
+                         .multiple(true)
+                         .help("Files or directories to convert. Files in a directory are converted in the order of their enumeration"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .help("Write to file instead of stdout"))
+                    .arg(Arg::with_name("overwrite")
+                         .long("overwrite")
+                         .requires("output")
+                         .help("Overwrite output file if present"))
+                    .arg(Arg::with_name("from")
+                         .long("from")
+                         .takes_value(true)
+                         .possible_values(&["csv", "json", "raw"])
+                         .help("Input format. Detected per line if omitted"))
+                    .arg(Arg::with_name("to")
+                         .long("to")
+                         .takes_value(true)
+                         .required(true)
+                         .possible_values(&["csv", "html", "json", "raw"])
+                         .help("Output format")))
+          // Diff
+          .subcommand(SubCommand::with_name("diff")
+                    .about("Compare two captures by message signature. Numbers, addresses, uuids and timestamps are masked")
+                    .arg(Arg::with_name("a")
+                         .required(true)
+                         .index(1)
+                         .help("First capture e.g of a passing run"))
+                    .arg(Arg::with_name("b")
+                         .required(true)
+                         .index(2)
+                         .help("Second capture e.g of a failing run"))
+                    .args(&profile_args())
+                    .args(&filter_args())
+                    .arg(Arg::with_name("ratio")
+                         .long("ratio")
+                         .takes_value(true)
+                         .help("Factor between two counts to report them as changed. Defaults to 2"))
+                    .arg(Arg::with_name("min-delta")
+                         .long("min-delta")
+                         .takes_value(true)
+                         .help("Minimum difference between two counts to report them as changed. Defaults to 5"))
+                    .arg(Arg::with_name("side-by-side")
+                         .short("y")
+                         .long("side-by-side")
+                         .help("Print both captures aligned in two columns"))
+                    .arg(Arg::with_name("width")
+                         .short("w")
+                         .long("width")
+                         .takes_value(true)
+                         .requires("side-by-side")
+                         .help("Total width of the side by side output. Defaults to the terminal width")))
+          // Crashes
+          .subcommand(SubCommand::with_name("crashes")
+                    .about("Detect native crashes and ANRs, write a report per crash and print a summary")
+                    .args(&source_args())
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .default_value("crashes")
+                         .help("Directory to write the crash reports to"))
+                    .arg(Arg::with_name("context")
+                         .short("c")
+                         .long("context")
+                         .takes_value(true)
+                         .help("Number of records of the crashing process to include in a report. Defaults to 50")))
+          // Profiles
+          .subcommand(SubCommand::with_name("profiles")
+                    .about("List, show, validate and save profiles")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .arg(Arg::with_name("profiles-path")
+                         .short("P")
+                         .long("profiles-path")
+                         .takes_value(true)
+                         .help("Manually specify profile file (overrules ROGCAT_PROFILES)"))
+                    .subcommand(SubCommand::with_name("list")
+                              .about("List the profiles and their comments"))
+                    .subcommand(SubCommand::with_name("show")
+                              .about("Print a profile with its extends resolved")
+                              .arg(Arg::with_name("name")
+                                   .required(true)
+                                   .help("Profile name")))
+                    .subcommand(SubCommand::with_name("validate")
+                              .about("Check the profiles for unknown fields, invalid regular expressions, triggers and options and cycles in extends"))
+                    .subcommand(SubCommand::with_name("save")
+                              .about("Save the filters of the command line as new profile")
+                              .arg(Arg::with_name("name")
+                                   .required(true)
+                                   .help("Profile name"))
+                              .arg(Arg::with_name("comment")
+                                   .short("c")
+                                   .long("comment")
+                                   .takes_value(true)
+                                   .help("Comment of the profile"))
+                              .arg(Arg::with_name("extends")
+                                   .short("e")
+                                   .long("extends")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .help("Profiles to extend"))
+                              .args(&filter_args())
+                              .arg(Arg::with_name("filter-case-insensitive")
+                                   .long("Filter")
+                                   .short("F")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .help("Same as -f/--filter but case insensitive"))
+                              .arg(Arg::with_name("message-case-insensitive")
+                                   .short("M")
+                                   .long("Message")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .help("Same as -m/--message but case insensitive"))
+                              .arg(Arg::with_name("tag-case-insensitive")
+                                   .short("T")
+                                   .long("Tag")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .help("Same as -t/--tag but case insensitive"))
+                              .arg(Arg::with_name("highlight")
+                                   .short("h")
+                                   .long("highlight")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .number_of_values(1)
+                                   .help("Highlight rule e.g \"FATAL => bold red\""))
+                              .arg(Arg::with_name("trigger")
+                                   .long("trigger")
+                                   .takes_value(true)
+                                   .multiple(true)
+                                   .number_of_values(1)
+                                   .allow_hyphen_values(true)
+                                   .help("Trigger in the format \"FILTER => ACTION\""))))
           // Completions
           .subcommand(SubCommand::with_name("completions")
                     .about("Generates completion scripts")
@@ -252,7 +669,10 @@ pub fn cli() -> App<'static, 'static> {
                               .help("Select specific log buffers to clear. Defaults to main, events, kernel and crash")))
           // List adb devices
           .subcommand(SubCommand::with_name("devices")
-                    .about("List available devices"))
+                    .about("List available devices")
+                    .arg(Arg::with_name("track")
+                         .long("track")
+                         .help("Print the device list whenever it changes")))
           // Log to logd
           .subcommand(SubCommand::with_name("log")
                     .about("Add log message(s) log buffer")
@@ -0,0 +1,262 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::filewriter;
+use failure::{format_err, Error};
+use rogcat::{
+    parser::{self, Parser},
+    record::{Format, Record},
+};
+use std::{
+    fs,
+    io::{BufRead, Write},
+    mem,
+    path::{Path, PathBuf},
+};
+
+/// Expand directories in `inputs` to the files they contain. The files of a
+/// directory are ordered by their enumeration e.g `log-2.txt` before `log-10.txt`.
+pub fn files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let mut entries = fs::read_dir(input)
+                .map_err(|e| format_err!("Failed to read {}: {}", input.display(), e))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_file())
+                .collect::<Vec<_>>();
+            entries.sort_by_key(|path| enumeration(path));
+            files.extend(entries);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Sort key of `path` with the trailing number of the file stem split off
+fn enumeration(path: &Path) -> (String, u64, String) {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = stem[prefix.len()..].parse().unwrap_or(0);
+    (prefix.to_owned(), number, name)
+}
+
+/// Formats records and writes them to `out`. Html is rendered on `finish`.
+pub struct Writer {
+    format: Format,
+    out: Box<dyn Write>,
+    html: Vec<Record>,
+}
+
+impl Writer {
+    pub fn new(format: Format, out: Box<dyn Write>) -> Result<Writer, Error> {
+        match format {
+            Format::Human => return Err(format_err!("Unsupported output format {}", format)),
+            Format::Sqlite => {
+                return Err(format_err!(
+                    "Unsupported output format {}: capture into a database with rogcat -o FILE.db",
+                    format
+                ))
+            }
+            _ => (),
+        }
+        Ok(Writer {
+            format,
+            out,
+            html: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, record: Record) -> Result<(), Error> {
+        if self.format == Format::Html {
+            self.html.push(record);
+        } else {
+            writeln!(self.out, "{}", self.format.fmt_record(&record)?)
+                .map_err(|e| format_err!("Failed to write: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        if self.format == Format::Html {
+            filewriter::write_html(mem::take(&mut self.html), &[], &mut self.out)?;
+        }
+        self.out
+            .flush()
+            .map_err(|e| format_err!("Failed to write: {}", e))
+    }
+}
+
+/// Parses lines in a fixed format or detects the format per line
+pub struct Converter {
+    from: Option<Format>,
+    parser: Parser,
+    /// Number of records written
+    pub records: usize,
+    /// Number of lines that failed to parse
+    pub failures: usize,
+}
+
+impl Converter {
+    pub fn new(from: Option<Format>) -> Converter {
+        Converter {
+            from,
+            parser: Parser::default(),
+            records: 0,
+            failures: 0,
+        }
+    }
+
+    fn parse(&mut self, line: String) -> Result<Record, String> {
+        match self.from {
+            Some(ref format) => parser::parse_format(format, &line).map_err(|e| e.to_string()),
+            None => {
+                // Prefer csv and json to keep the raw field of the records
+                for format in &[Format::Json, Format::Csv] {
+                    if let Ok(record) = parser::parse_format(format, &line) {
+                        return Ok(record);
+                    }
+                }
+                self.parser
+                    .try_parse(line)
+                    .map_err(|_| "Unknown format".to_owned())
+            }
+        }
+    }
+
+    /// Convert the lines of `reader` and write them to `writer`. Lines that
+    /// fail to parse are reported with `name` and line number. Unparsed raw
+    /// lines are kept as message.
+    pub fn convert<R: BufRead>(
+        &mut self,
+        name: &str,
+        mut reader: R,
+        writer: &mut Writer,
+    ) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        for number in 1.. {
+            buffer.clear();
+            if reader
+                .read_until(b'\n', &mut buffer)
+                .map_err(|e| format_err!("Failed to read {}: {}", name, e))?
+                == 0
+            {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer)
+                .trim_end_matches(['\r', '\n'])
+                .to_owned();
+            if line.is_empty() {
+                continue;
+            }
+
+            let record = match self.parse(line.clone()) {
+                Ok(record) => record,
+                Err(e) => {
+                    self.failures += 1;
+                    eprintln!("{name}:{number}: {e}");
+                    match self.from {
+                        Some(Format::Csv) | Some(Format::Json) => continue,
+                        _ => Record {
+                            message: line.clone(),
+                            raw: line,
+                            ..Default::default()
+                        },
+                    }
+                }
+            };
+            writer.write(record)?;
+            self.records += 1;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_enumeration() {
+    let mut files = ["log-010.txt", "log-2.txt", "log-000.txt", "log.txt"]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    files.sort_by_key(|path| enumeration(path));
+    assert_eq!(
+        files,
+        ["log.txt", "log-000.txt", "log-2.txt", "log-010.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_convert() {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    /// Shared buffer to inspect the output of a `Writer`
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn convert(from: Option<Format>, to: Format, input: &str) -> (String, usize) {
+        let buffer = Buffer::default();
+        let mut writer = Writer::new(to, Box::new(buffer.clone())).unwrap();
+        let mut converter = Converter::new(from);
+        converter
+            .convert("test", Cursor::new(input), &mut writer)
+            .unwrap();
+        writer.finish().unwrap();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        (output, converter.failures)
+    }
+
+    let raw = "01-02 03:04:05.678  100  200 W Tag: hello, world\nnot a record\n";
+    let (json, failures) = convert(Some(Format::Raw), Format::Json, raw);
+    assert_eq!(failures, 1);
+    let (csv, _) = convert(Some(Format::Json), Format::Csv, &json);
+    let (json2, failures) = convert(Some(Format::Csv), Format::Json, &csv);
+    assert_eq!(failures, 0);
+    assert_eq!(json, json2);
+    let (raw2, _) = convert(None, Format::Raw, &csv);
+    assert_eq!(raw, raw2);
+
+    let (_, failures) = convert(Some(Format::Json), Format::Csv, "{\n");
+    assert_eq!(failures, 1);
+
+    assert!(Writer::new(Format::Sqlite, Box::new(Buffer::default())).is_err());
+}
@@ -0,0 +1,687 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::{format_err, Error};
+use futures::{Future, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::{Record, Timestamp};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::{Display, Formatter},
+    fs::{DirBuilder, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::timer::Interval;
+
+/// Number of unrelated records after which an open crash block is closed
+const IDLE_LIMIT: usize = 256;
+/// Time without records of its own after which an open crash block is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of records after which the history of a silent process is dropped
+const HISTORY_IDLE: usize = 10_000;
+/// Default number of records of the crashing process kept as context
+pub const DEFAULT_CONTEXT: usize = 50;
+
+lazy_static! {
+    static ref NATIVE_PID: Regex =
+        Regex::new(r"^pid: (\d+), tid: \d+, name: .*?>>> (.*) <<<").unwrap();
+    static ref SIGNAL: Regex =
+        Regex::new(r"^signal \d+ \((\w+)\), code -?\d+ \(\w+\)(?:, fault addr (\S+))?").unwrap();
+    static ref ABORT: Regex = Regex::new(r"^Abort message: '(.*)'$").unwrap();
+    static ref FRAME: Regex = Regex::new(r"^#\d+ pc [0-9a-fA-F]+\s+(\S+)(?: \((.*?)\))?").unwrap();
+    static ref ANR_IN: Regex = Regex::new(r"^ANR in (\S+)").unwrap();
+    static ref ANR_PID: Regex = Regex::new(r"^PID: (\d+)").unwrap();
+    static ref ANR_REASON: Regex = Regex::new(r"^Reason: (.*)").unwrap();
+    static ref AM_ANR: Regex = Regex::new(r"^\[\d+,(\d+),([^,]+),\d+,(.*)\]$").unwrap();
+    static ref PROCESS_DIED: Regex = Regex::new(r"^Process \S+ \(pid (\d+)\) has died").unwrap();
+    static ref AM_PROC_DIED: Regex = Regex::new(r"^\[\d+,(\d+),").unwrap();
+    static ref NUMBER: Regex = Regex::new(r"\d+").unwrap();
+}
+
+/// Crash type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Native crash reported by `debuggerd`
+    Native,
+    /// Application not responding
+    Anr,
+}
+
+impl Kind {
+    /// Tag of the records that make up a crash block
+    fn tag(self) -> &'static str {
+        match self {
+            Kind::Native => "DEBUG",
+            Kind::Anr => "ActivityManager",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Kind::Native => "native",
+                Kind::Anr => "anr",
+            }
+        )
+    }
+}
+
+/// A detected crash with all collected information
+#[derive(Clone, Debug)]
+pub struct Crash {
+    pub kind: Kind,
+    pub process: String,
+    pub pid: String,
+    pub signal: Option<String>,
+    pub fault_addr: Option<String>,
+    pub abort_message: Option<String>,
+    pub reason: Option<String>,
+    pub backtrace: Vec<String>,
+    /// Records of the crash block
+    pub records: Vec<Record>,
+    /// Records of the crashing process prior to the crash
+    pub context: Vec<Record>,
+}
+
+impl Crash {
+    fn new(kind: Kind) -> Crash {
+        Crash {
+            kind,
+            process: String::new(),
+            pid: String::new(),
+            signal: None,
+            fault_addr: None,
+            abort_message: None,
+            reason: None,
+            backtrace: Vec::new(),
+            records: Vec::new(),
+            context: Vec::new(),
+        }
+    }
+
+    /// Signature used to group crashes. Numbers in ANR reasons are masked.
+    pub fn signature(&self) -> String {
+        match self.kind {
+            Kind::Native => {
+                let signal = self.signal.as_deref().unwrap_or("unknown signal");
+                let frame = self
+                    .backtrace
+                    .first()
+                    .and_then(|f| FRAME.captures(f))
+                    .map(|c| {
+                        let module = c[1].rsplit('/').next().unwrap_or(&c[1]).to_owned();
+                        match c.get(2).map(|s| s.as_str()) {
+                            Some(symbol) => {
+                                let symbol = symbol.rsplit_once('+').map_or(symbol, |(s, _)| s);
+                                format!("{module} {symbol}")
+                            }
+                            None => module,
+                        }
+                    });
+                match frame {
+                    Some(frame) => format!("{signal} {frame}"),
+                    None => signal.to_owned(),
+                }
+            }
+            Kind::Anr => {
+                let reason = self.reason.as_deref().unwrap_or("unknown reason");
+                format!("ANR {}", NUMBER.replace_all(reason, "#"))
+            }
+        }
+    }
+
+    /// Write a report into a new file in `dir` and return its path
+    pub fn write_report(&self, dir: &Path) -> Result<PathBuf, Error> {
+        if !dir.is_dir() {
+            DirBuilder::new().recursive(true).create(dir).map_err(|e| {
+                format_err!("Failed to create report directory {}: {}", dir.display(), e)
+            })?;
+        }
+
+        let process = self
+            .process
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        let filename = (0..)
+            .map(|i| {
+                dir.join(format!(
+                    "{:03}-{}-{}-{}.txt",
+                    i, self.kind, process, self.pid
+                ))
+            })
+            .find(|f| !f.exists())
+            .expect("Failed to find report filename");
+
+        let mut file = File::create(&filename)
+            .map_err(|e| format_err!("Failed to create {}: {}", filename.display(), e))?;
+
+        writeln!(file, "Type: {}", self.kind)?;
+        writeln!(file, "Process: {} ({})", self.process, self.pid)?;
+        writeln!(file, "Signature: {}", self.signature())?;
+        if let Some(ref signal) = self.signal {
+            writeln!(file, "Signal: {signal}")?;
+        }
+        if let Some(ref fault_addr) = self.fault_addr {
+            writeln!(file, "Fault address: {fault_addr}")?;
+        }
+        if let Some(ref abort_message) = self.abort_message {
+            writeln!(file, "Abort message: {abort_message}")?;
+        }
+        if let Some(ref reason) = self.reason {
+            writeln!(file, "Reason: {reason}")?;
+        }
+
+        if !self.backtrace.is_empty() {
+            writeln!(file, "\nBacktrace:")?;
+            for frame in &self.backtrace {
+                writeln!(file, "  {frame}")?;
+            }
+        }
+
+        writeln!(file, "\nCrash:")?;
+        for record in &self.records {
+            writeln!(file, "{}", record.raw)?;
+        }
+
+        writeln!(file, "\nPreceding logs of {}:", self.pid)?;
+        for record in &self.context {
+            writeln!(file, "{}", record.raw)?;
+        }
+
+        Ok(filename)
+    }
+}
+
+/// A crash block that is still collecting records
+struct Open {
+    crash: Crash,
+    /// Process that emits the block e.g crash_dump or system_server
+    source: String,
+    /// Timestamp and thread of the log entry of an ANR. The lines of an entry
+    /// are logged in one go and the block ends with the first other record.
+    entry: Option<(Option<Timestamp>, String)>,
+    idle: usize,
+    /// Time of the last record of the block
+    last: Instant,
+    in_backtrace: bool,
+    done: bool,
+}
+
+impl Open {
+    fn new(kind: Kind, record: &Record) -> Open {
+        Open {
+            crash: Crash::new(kind),
+            source: record.process.clone(),
+            entry: None,
+            idle: 0,
+            last: Instant::now(),
+            in_backtrace: false,
+            done: false,
+        }
+    }
+
+    fn in_entry(&self, record: &Record) -> bool {
+        self.entry
+            .as_ref()
+            .map(|(timestamp, thread)| *timestamp == record.timestamp && *thread == record.thread)
+            .unwrap_or(true)
+    }
+
+    fn accepts(&self, record: &Record) -> bool {
+        record.process == self.source
+            && record.tags.first().map(String::as_str) == Some(self.crash.kind.tag())
+    }
+
+    fn add(&mut self, record: &Record) {
+        let message = record.message.trim();
+        let crash = &mut self.crash;
+        crash.records.push(record.clone());
+        self.idle = 0;
+        self.last = Instant::now();
+
+        match crash.kind {
+            Kind::Native => {
+                if let Some(c) = NATIVE_PID.captures(message) {
+                    crash.pid = c[1].to_owned();
+                    crash.process = c[2].to_owned();
+                } else if let Some(c) = SIGNAL.captures(message) {
+                    crash.signal = Some(c[1].to_owned());
+                    crash.fault_addr = c.get(2).map(|m| m.as_str().to_owned());
+                } else if let Some(c) = ABORT.captures(message) {
+                    crash.abort_message = Some(c[1].to_owned());
+                } else if message == "backtrace:" {
+                    self.in_backtrace = crash.backtrace.is_empty();
+                } else if self.in_backtrace && FRAME.is_match(message) {
+                    crash.backtrace.push(message.to_owned());
+                } else if message.starts_with("Tombstone written to") {
+                    self.done = true;
+                } else {
+                    self.in_backtrace = false;
+                }
+            }
+            Kind::Anr => {
+                if let Some(c) = ANR_IN.captures(message) {
+                    crash.process = c[1].to_owned();
+                } else if let Some(c) = ANR_PID.captures(message) {
+                    crash.pid = c[1].to_owned();
+                } else if let Some(c) = ANR_REASON.captures(message) {
+                    crash.reason = Some(c[1].to_owned());
+                }
+            }
+        }
+    }
+}
+
+/// Detects native crashes and ANRs in a stream of records
+pub struct Detector {
+    context: usize,
+    /// Recent records and the number of the last record of each process
+    history: HashMap<String, (usize, VecDeque<Record>)>,
+    /// Number of processed records
+    records: usize,
+    open: Vec<Open>,
+}
+
+impl Detector {
+    pub fn new(context: usize) -> Detector {
+        Detector {
+            context,
+            history: HashMap::new(),
+            records: 0,
+            open: Vec::new(),
+        }
+    }
+
+    /// Feed a record and return the crashes that are completed
+    pub fn process(&mut self, record: &Record) -> Vec<Crash> {
+        let tag = record.tags.first().map(String::as_str).unwrap_or("");
+        let message = record.message.trim();
+
+        let mut finished = Vec::new();
+
+        if tag == Kind::Native.tag() && message.starts_with("*** *** ***") {
+            // A new header from the same source closes the previous block
+            for open in &mut self.open {
+                if open.crash.kind == Kind::Native && open.source == record.process {
+                    open.done = true;
+                }
+            }
+            finished.extend(self.take_done());
+            let mut open = Open::new(Kind::Native, record);
+            open.add(record);
+            self.open.push(open);
+        } else if tag == "am_anr" {
+            if let Some(c) = AM_ANR.captures(message) {
+                let open = self.anr(&c[2], record);
+                open.crash.records.push(record.clone());
+                open.crash.pid = c[1].to_owned();
+                open.crash.reason.get_or_insert_with(|| c[3].to_owned());
+            }
+        } else if let Some(c) = ANR_IN.captures(message).filter(|_| tag == Kind::Anr.tag()) {
+            let open = self.anr(&c[1], record);
+            open.source = record.process.clone();
+            open.entry = Some((record.timestamp.clone(), record.thread.clone()));
+            open.add(record);
+        } else {
+            for open in &mut self.open {
+                if open.accepts(record) && open.in_entry(record) {
+                    open.add(record);
+                } else if open.entry.is_some() {
+                    open.done = true;
+                } else {
+                    open.idle += 1;
+                    open.done |= open.idle > IDLE_LIMIT;
+                }
+            }
+        }
+
+        // Snapshot the preceding logs as soon as the crashing pid is known
+        for open in &mut self.open {
+            if open.crash.context.is_empty() && !open.crash.pid.is_empty() {
+                if let Some((_, history)) = self.history.get(&open.crash.pid) {
+                    open.crash.context = history.iter().cloned().collect();
+                }
+            }
+        }
+
+        self.records += 1;
+        if self.context > 0 {
+            let (last, history) = self.history.entry(record.process.clone()).or_default();
+            *last = self.records;
+            if history.len() == self.context {
+                history.pop_front();
+            }
+            history.push_back(record.clone());
+        }
+
+        // Forget processes that died or stayed silent for a while
+        let died = match tag {
+            "ActivityManager" => PROCESS_DIED.captures(message),
+            "am_proc_died" => AM_PROC_DIED.captures(message),
+            _ => None,
+        };
+        if let Some(c) = died {
+            self.history.remove(&c[1]);
+        }
+        if self.records.is_multiple_of(HISTORY_IDLE) {
+            let records = self.records;
+            self.history
+                .retain(|_, (last, _)| records - *last < HISTORY_IDLE);
+        }
+
+        finished.extend(self.take_done());
+        finished
+    }
+
+    /// Close the blocks that did not get a record for a while e.g the last
+    /// ANR on a quiet device
+    pub fn expire(&mut self, now: Instant) -> Vec<Crash> {
+        for open in &mut self.open {
+            open.done |= now.duration_since(open.last) >= IDLE_TIMEOUT;
+        }
+        self.take_done()
+    }
+
+    /// Close all open blocks e.g on end of stream
+    pub fn finish(&mut self) -> Vec<Crash> {
+        self.open.drain(..).map(|o| o.crash).collect()
+    }
+
+    /// Find the open ANR of `process` or open a new one
+    fn anr(&mut self, process: &str, record: &Record) -> &mut Open {
+        let index = match self
+            .open
+            .iter()
+            .position(|o| o.crash.kind == Kind::Anr && o.crash.process == process)
+        {
+            Some(index) => index,
+            None => {
+                let mut open = Open::new(Kind::Anr, record);
+                open.crash.process = process.to_owned();
+                self.open.push(open);
+                self.open.len() - 1
+            }
+        };
+        let open = &mut self.open[index];
+        open.idle = 0;
+        open.last = Instant::now();
+        open
+    }
+
+    fn take_done(&mut self) -> Vec<Crash> {
+        let (done, open) = self.open.drain(..).partition(|o| o.done);
+        self.open = open;
+        done.into_iter().map(|o: Open| o.crash).collect()
+    }
+}
+
+/// Crash counts per process and signature
+#[derive(Default)]
+pub struct Summary {
+    processes: BTreeMap<String, usize>,
+    signatures: BTreeMap<String, usize>,
+}
+
+impl Summary {
+    pub fn add(&mut self, crash: &Crash) {
+        *self.processes.entry(crash.process.clone()).or_default() += 1;
+        *self.signatures.entry(crash.signature()).or_default() += 1;
+    }
+
+    /// Write the counts to `out` e.g stdout or stderr
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        fn table(
+            out: &mut dyn Write,
+            title: &str,
+            counts: &BTreeMap<String, usize>,
+        ) -> io::Result<()> {
+            let mut rows = counts.iter().collect::<Vec<_>>();
+            rows.sort_by(|a, b| b.1.cmp(a.1));
+            let width = rows
+                .iter()
+                .map(|(k, _)| k.chars().count())
+                .chain(Some(title.len()))
+                .max()
+                .unwrap_or(0);
+            writeln!(out, "{title:width$}  Count")?;
+            for (key, count) in rows {
+                writeln!(out, "{key:width$}  {count:>5}")?;
+            }
+            Ok(())
+        }
+
+        let total: usize = self.processes.values().sum();
+        writeln!(out, "{total} crash(es) found")?;
+        if total > 0 {
+            writeln!(out)?;
+            table(out, "Process", &self.processes)?;
+            writeln!(out)?;
+            table(out, "Signature", &self.signatures)?;
+        }
+        Ok(())
+    }
+}
+
+/// Detector that writes a report file for each crash
+pub struct Reports {
+    dir: PathBuf,
+    detector: Detector,
+    pub summary: Summary,
+}
+
+impl Reports {
+    pub fn new(dir: PathBuf, context: usize) -> Reports {
+        Reports {
+            dir,
+            detector: Detector::new(context),
+            summary: Summary::default(),
+        }
+    }
+
+    pub fn process(&mut self, record: &Record) -> Result<(), Error> {
+        let crashes = self.detector.process(record);
+        self.write(crashes)
+    }
+
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let crashes = self.detector.finish();
+        self.write(crashes)
+    }
+
+    pub fn expire(&mut self) -> Result<(), Error> {
+        let crashes = self.detector.expire(Instant::now());
+        self.write(crashes)
+    }
+
+    fn write(&mut self, crashes: Vec<Crash>) -> Result<(), Error> {
+        for crash in crashes {
+            let filename = crash.write_report(&self.dir)?;
+            eprintln!(
+                "Wrote {} report of {} to {}",
+                crash.kind,
+                crash.process,
+                filename.display()
+            );
+            self.summary.add(&crash);
+        }
+        Ok(())
+    }
+}
+
+/// Periodically write the reports of crash blocks that stopped receiving
+/// records
+pub fn expire(reports: Arc<Mutex<Reports>>) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(Duration::from_secs(1))
+        .map_err(|e| eprintln!("{e}"))
+        .for_each(move |_| {
+            if let Err(e) = reports.lock().expect("Failed to lock reports").expire() {
+                eprintln!("{e}");
+            }
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+fn records(lines: &str) -> Vec<Record> {
+    let mut parser = rogcat::parser::Parser::default();
+    lines
+        .lines()
+        .map(|l| parser.parse(l.trim_start().to_owned()))
+        .collect()
+}
+
+#[test]
+fn test_native() {
+    let lines = "
+        03-15 10:23:44.900  1234  1250 E libc    : Check failed: buffer != nullptr
+        03-15 10:23:45.123  5678  5678 F DEBUG   : *** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***
+        03-15 10:23:45.123  5678  5678 F DEBUG   : Build fingerprint: 'google/sargo/sargo:11/RQ3A.210805.001.A1/7474174:user/release-keys'
+        03-15 10:23:45.123  5678  5678 F DEBUG   : Revision: 'MP1.0'
+        03-15 10:23:45.123  5678  5678 F DEBUG   : ABI: 'arm64'
+        03-15 10:23:45.124  5678  5678 F DEBUG   : pid: 1234, tid: 1250, name: RenderThread  >>> com.example.app <<<
+        03-15 10:23:45.124  5678  5678 F DEBUG   : uid: 10123
+        03-15 10:23:45.124  5678  5678 F DEBUG   : signal 6 (SIGABRT), code -1 (SI_QUEUE), fault addr --------
+        03-15 10:23:45.124  5678  5678 F DEBUG   : Abort message: 'Check failed: buffer != nullptr'
+        03-15 10:23:45.124  5678  5678 F DEBUG   :     x0  0000000000000000  x1  00000000000004e2  x2  0000000000000006  x3  0000007fd8e1b7b0
+        03-15 10:23:45.125  1000  1100 I ActivityManager: Process com.example.app (pid 1234) has died
+        03-15 10:23:45.130  5678  5678 F DEBUG   : backtrace:
+        03-15 10:23:45.130  5678  5678 F DEBUG   :       #00 pc 000000000004e0e4  /apex/com.android.runtime/lib64/bionic/libc.so (abort+164) (BuildId: 3e5e1b5bd0a2b2b2)
+        03-15 10:23:45.130  5678  5678 F DEBUG   :       #01 pc 00000000000a1b2c  /system/lib64/libhwui.so (android::uirenderer::renderthread::RenderThread::threadLoop()+220)
+        03-15 10:23:45.130  5678  5678 F DEBUG   :       #02 pc 0000000000014f3c  /system/lib64/libutils.so
+        03-15 10:23:45.200  5678  5678 E DEBUG   : Tombstone written to: /data/tombstones/tombstone_03";
+
+    let mut detector = Detector::new(DEFAULT_CONTEXT);
+    let crashes = records(lines.trim())
+        .iter()
+        .flat_map(|r| detector.process(r))
+        .collect::<Vec<_>>();
+    assert!(detector.finish().is_empty());
+    assert_eq!(crashes.len(), 1);
+
+    let crash = &crashes[0];
+    assert_eq!(crash.kind, Kind::Native);
+    assert_eq!(crash.process, "com.example.app");
+    assert_eq!(crash.pid, "1234");
+    assert_eq!(crash.signal.as_deref(), Some("SIGABRT"));
+    assert_eq!(crash.fault_addr.as_deref(), Some("--------"));
+    assert_eq!(
+        crash.abort_message.as_deref(),
+        Some("Check failed: buffer != nullptr")
+    );
+    assert_eq!(crash.backtrace.len(), 3);
+    assert_eq!(crash.signature(), "SIGABRT libc.so abort");
+    // The ActivityManager record is not part of the block
+    assert_eq!(crash.records.len(), 14);
+    assert_eq!(crash.context.len(), 1);
+    assert_eq!(crash.context[0].message, "Check failed: buffer != nullptr");
+
+    // Frames without symbol use the module only
+    let crash = Crash {
+        backtrace: vec!["#00 pc 0000000000014f3c  /system/lib64/libutils.so".into()],
+        signal: Some("SIGSEGV".into()),
+        ..Crash::new(Kind::Native)
+    };
+    assert_eq!(crash.signature(), "SIGSEGV libutils.so");
+    assert_eq!(Crash::new(Kind::Native).signature(), "unknown signal");
+}
+
+#[test]
+fn test_anr() {
+    let lines = "
+        03-15 10:30:00.000  1000  1100 I am_anr  : [0,4321,com.example.app,952745542,Input dispatching timed out (Waiting to send non-key event because the touched window has not finished processing certain input events that were delivered to it over 500.0ms ago.)]
+        03-15 10:30:00.100  1000  1100 E ActivityManager: ANR in com.example.app (com.example.app/.MainActivity)
+        03-15 10:30:00.100  1000  1100 E ActivityManager: PID: 4321
+        03-15 10:30:00.100  1000  1100 E ActivityManager: Reason: Input dispatching timed out (Waiting to send non-key event because the touched window has not finished processing certain input events that were delivered to it over 500.0ms ago.)
+        03-15 10:30:00.100  1000  1100 E ActivityManager: Load: 12.5 / 10.1 / 8.3
+        03-15 10:30:00.200  2000  2001 I Other   : unrelated";
+
+    let mut detector = Detector::new(0);
+    let crashes = records(lines.trim())
+        .iter()
+        .flat_map(|r| detector.process(r))
+        .collect::<Vec<_>>();
+    // The unrelated record ends the ANR entry
+    assert!(detector.finish().is_empty());
+    assert_eq!(crashes.len(), 1);
+
+    let crash = &crashes[0];
+    assert_eq!(crash.kind, Kind::Anr);
+    assert_eq!(crash.process, "com.example.app");
+    assert_eq!(crash.pid, "4321");
+    assert_eq!(crash.records.len(), 5);
+    assert_eq!(
+        crash.signature(),
+        "ANR Input dispatching timed out (Waiting to send non-key event because the touched window has not finished processing certain input events that were delivered to it over #.#ms ago.)"
+    );
+
+    // am_anr alone provides pid and reason and is closed after a while
+    let mut detector = Detector::new(0);
+    detector.process(&records(lines.trim())[0]);
+    assert!(detector.expire(Instant::now()).is_empty());
+    let crash = detector.expire(Instant::now() + IDLE_TIMEOUT).remove(0);
+    assert_eq!(crash.pid, "4321");
+    assert_eq!(crash.signature(), crashes[0].signature());
+
+    let mut summary = Summary::default();
+    summary.add(&crashes[0]);
+    summary.add(&crash);
+    let mut out = Vec::new();
+    summary.print(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("2 crash(es) found\n"));
+    assert!(out.contains("com.example.app      2"));
+}
+
+#[test]
+fn test_history() {
+    let lines = "
+        03-15 10:23:44.900  1234  1250 E libc    : Check failed: buffer != nullptr
+        03-15 10:23:44.900  4321  4321 I Foo     : bar
+        03-15 10:23:45.125  1000  1100 I ActivityManager: Process com.example.app (pid 1234) has died";
+
+    let mut detector = Detector::new(DEFAULT_CONTEXT);
+    for record in records(lines.trim()) {
+        detector.process(&record);
+    }
+    assert!(!detector.history.contains_key("1234"));
+    assert!(detector.history.contains_key("4321"));
+
+    let record = &records("03-15 10:23:46.000  1000  1100 I Foo     : bar")[0];
+    for _ in 0..2 * HISTORY_IDLE {
+        detector.process(record);
+    }
+    assert!(!detector.history.contains_key("4321"));
+    assert!(detector.history.contains_key("1000"));
+}
@@ -0,0 +1,351 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::filter::Filter;
+use failure::{format_err, Error};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::{parser::Parser, record::Record};
+use std::{cmp::Ordering, collections::HashMap, io::BufRead};
+use unicode_width::UnicodeWidthChar;
+
+/// Default factor between two counts to be reported as changed
+pub const DEFAULT_RATIO: f64 = 2.0;
+/// Default minimum difference between two counts to be reported as changed
+pub const DEFAULT_MIN_DELTA: usize = 5;
+/// Limit of the alignment table of the side by side mode
+const MAX_CELLS: usize = 16_000_000;
+
+lazy_static! {
+    static ref TIMESTAMP: Regex =
+        Regex::new(r"(\d{4}-\d{2}-\d{2}[ T])?\d{1,2}:\d{2}:\d{2}([.,]\d+)?").unwrap();
+    static ref UUID: Regex = Regex::new(
+        r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b"
+    )
+    .unwrap();
+    static ref NUMBER: Regex =
+        Regex::new(r"(0x[0-9a-fA-F]+|\b[0-9a-fA-F]*\d[0-9a-fA-F]*\b|\d+)").unwrap();
+}
+
+/// Mask timestamps, uuids, numbers and hex addresses in `message` that
+/// differ between two runs
+fn normalize(message: &str) -> String {
+    let message = TIMESTAMP.replace_all(message, "<time>");
+    let message = UUID.replace_all(&message, "<uuid>");
+    NUMBER.replace_all(&message, "#").into_owned()
+}
+
+/// Signature of `record` that groups records with similar messages
+pub fn signature(record: &Record) -> String {
+    format!(
+        "{} {}: {}",
+        record.level,
+        record.tags.join(","),
+        normalize(&record.message)
+    )
+}
+
+/// Consecutive records with the same signature
+#[derive(Debug)]
+struct Run {
+    signature: String,
+    /// Tag and message of the first record
+    text: String,
+    count: usize,
+}
+
+/// Signature counts and runs of a capture
+#[derive(Debug, Default)]
+pub struct Capture {
+    counts: HashMap<String, usize>,
+    runs: Vec<Run>,
+}
+
+impl Capture {
+    /// Parse the lines of `reader` and count the records that pass `filter`
+    pub fn read<R: BufRead>(mut reader: R, filter: &Filter) -> Result<Capture, Error> {
+        let mut capture = Capture::default();
+        let mut parser = Parser::default();
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            if reader
+                .read_until(b'\n', &mut buffer)
+                .map_err(|e| format_err!("Failed to read: {}", e))?
+                == 0
+            {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer)
+                .trim_end_matches(['\r', '\n'])
+                .to_owned();
+            let record = parser.parse(line);
+            if filter.filter(&record) {
+                capture.add(&record);
+            }
+        }
+        Ok(capture)
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        let signature = signature(record);
+        *self.counts.entry(signature.clone()).or_default() += 1;
+        match self.runs.last_mut() {
+            Some(run) if run.signature == signature => run.count += 1,
+            _ => self.runs.push(Run {
+                signature,
+                text: format!("{}: {}", record.tags.join(","), record.message),
+                count: 1,
+            }),
+        }
+    }
+}
+
+/// Counts of a signature in capture a and b
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub signature: String,
+    pub a: usize,
+    pub b: usize,
+}
+
+/// Differences of two captures
+#[derive(Debug, Default)]
+pub struct Report {
+    pub only_a: Vec<Change>,
+    pub only_b: Vec<Change>,
+    pub changed: Vec<Change>,
+}
+
+/// Compare the signature counts of `a` and `b`. Counts that differ by at
+/// least `ratio` and `min_delta` are reported as changed.
+pub fn compare(a: &Capture, b: &Capture, ratio: f64, min_delta: usize) -> Report {
+    let mut report = Report::default();
+    let signatures = a
+        .counts
+        .keys()
+        .chain(b.counts.keys().filter(|s| !a.counts.contains_key(*s)));
+    for signature in signatures {
+        let change = Change {
+            signature: signature.clone(),
+            a: a.counts.get(signature).copied().unwrap_or(0),
+            b: b.counts.get(signature).copied().unwrap_or(0),
+        };
+        let (min, max) = (change.a.min(change.b), change.a.max(change.b));
+        if change.b == 0 {
+            report.only_a.push(change);
+        } else if change.a == 0 {
+            report.only_b.push(change);
+        } else if max - min >= min_delta && max as f64 >= min as f64 * ratio {
+            report.changed.push(change);
+        }
+    }
+
+    let by_count = |x: &Change, y: &Change| {
+        (y.a + y.b)
+            .cmp(&(x.a + x.b))
+            .then_with(|| x.signature.cmp(&y.signature))
+    };
+    report.only_a.sort_by(by_count);
+    report.only_b.sort_by(by_count);
+    report.changed.sort_by(|x, y| {
+        let delta = |c: &Change| (c.a as isize - c.b as isize).abs();
+        delta(y)
+            .cmp(&delta(x))
+            .then_with(|| x.signature.cmp(&y.signature))
+    });
+    report
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn print(&self) {
+        fn section(title: &str, changes: &[Change], count: fn(&Change) -> String) {
+            if changes.is_empty() {
+                return;
+            }
+            println!("{} ({}):", title, changes.len());
+            for change in changes {
+                println!("  {:>15}  {}", count(change), change.signature);
+            }
+            println!();
+        }
+
+        section("Only in A", &self.only_a, |c| c.a.to_string());
+        section("Only in B", &self.only_b, |c| c.b.to_string());
+        section("Changed", &self.changed, |c| format!("{} -> {}", c.a, c.b));
+        if self.is_empty() {
+            println!("No differences");
+        }
+    }
+}
+
+/// Align the runs of `a` and `b` and format them in two columns of `width`
+/// characters in total. `<` and `>` mark runs only present in a or b.
+pub fn side_by_side(a: &Capture, b: &Capture, width: usize) -> Result<Vec<String>, Error> {
+    let (n, m) = (a.runs.len(), b.runs.len());
+    if (n + 1) * (m + 1) > MAX_CELLS {
+        return Err(format_err!(
+            "Captures too large to align ({} and {} runs). Use filters to reduce them.",
+            n,
+            m
+        ));
+    }
+
+    // Longest common subsequence of the run signatures
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if a.runs[i].signature == b.runs[j].signature {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+
+    let column = width.saturating_sub(3) / 2;
+    let cell = |run: Option<&Run>| -> String {
+        let text = match run {
+            Some(run) if run.count > 1 => format!("{} (x{})", run.text, run.count),
+            Some(run) => run.text.clone(),
+            None => String::new(),
+        };
+        // Truncate and pad by display width e.g for CJK
+        let mut used = 0;
+        let mut cell = String::new();
+        for c in text.chars() {
+            let width = c.width().unwrap_or(0);
+            if used + width > column {
+                break;
+            }
+            used += width;
+            cell.push(c);
+        }
+        cell.extend((used..column).map(|_| ' '));
+        cell
+    };
+    let row = |left: Option<&Run>, marker: char, right: Option<&Run>| {
+        format!("{} {} {}", cell(left), marker, cell(right))
+            .trim_end()
+            .to_owned()
+    };
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a.runs[i].signature == b.runs[j].signature {
+            let marker = match a.runs[i].count.cmp(&b.runs[j].count) {
+                Ordering::Equal => ' ',
+                _ => '|',
+            };
+            lines.push(row(Some(&a.runs[i]), marker, Some(&b.runs[j])));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[at(i + 1, j)] >= lcs[at(i, j + 1)]) {
+            lines.push(row(Some(&a.runs[i]), '<', None));
+            i += 1;
+        } else {
+            lines.push(row(None, '>', Some(&b.runs[j])));
+            j += 1;
+        }
+    }
+    Ok(lines)
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize("pid 123 died"), "pid # died");
+    assert_eq!(normalize("fault addr 0x7f3a2b1c00"), "fault addr #");
+    assert_eq!(normalize("at 7f3a2b1c00 in libc"), "at # in libc");
+    assert_eq!(
+        normalize("deadbeef is not a face"),
+        "deadbeef is not a face"
+    );
+    assert_eq!(
+        normalize("session 123e4567-e89b-12d3-a456-426614174000 started"),
+        "session <uuid> started"
+    );
+    assert_eq!(
+        normalize("since 2019-05-01 12:00:01.123 and 7:03:04"),
+        "since <time> and <time>"
+    );
+}
+
+#[test]
+fn test_diff() {
+    let capture = |messages: &[&str]| {
+        let mut capture = Capture::default();
+        for message in messages {
+            capture.add(&Record {
+                tags: vec!["T".into()],
+                message: (*message).into(),
+                ..Default::default()
+            });
+        }
+        capture
+    };
+    let a = capture(&["start 1", "foo", "tick", "tick", "bar", "stop 1"]);
+    let b = capture(&["start 2", "tick", "baz", "tick", "stop 2"]);
+
+    let report = compare(&a, &b, 2.0, 1);
+    let signatures = |changes: &[Change]| {
+        changes
+            .iter()
+            .map(|c| c.signature.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(signatures(&report.only_a), vec!["- T: bar", "- T: foo"]);
+    assert_eq!(signatures(&report.only_b), vec!["- T: baz"]);
+    assert!(report.changed.is_empty());
+    let report = compare(&a, &capture(&["tick"; 6]), 2.0, 1);
+    assert_eq!(
+        report.changed,
+        vec![Change {
+            signature: "- T: tick".into(),
+            a: 2,
+            b: 6
+        }]
+    );
+
+    let lines = side_by_side(&a, &b, 27).unwrap();
+    assert_eq!(
+        lines,
+        vec![
+            "T: start 1     T: start 2",
+            "T: foo       <",
+            "T: tick (x2) | T: tick",
+            "T: bar       <",
+            "             > T: baz",
+            "             > T: tick",
+            "T: stop 1      T: stop 2",
+        ]
+    );
+
+    // Columns are measured in display width
+    let lines = side_by_side(&capture(&["日本語です"]), &capture(&["abc"]), 17).unwrap();
+    assert_eq!(lines, vec!["T: 日本 <", "        > T: abc"]);
+    let lines = side_by_side(&capture(&["日本語です"]), &capture(&["abc"]), 15).unwrap();
+    assert_eq!(lines[0], "T: 日  <");
+}
@@ -0,0 +1,221 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::replay::parse_duration;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{try_ready, Async, Poll, Stream};
+use regex::Regex;
+use rogcat::record::Record;
+use std::time::Duration;
+use time::Tm;
+
+/// Gaps flagged by default if the time is not absolute
+const DEFAULT_GAP: Duration = Duration::from_secs(1);
+
+/// Reference of the time column
+#[derive(Clone, Debug)]
+pub enum Mode {
+    /// Time since the first record
+    Relative,
+    /// Time since the previous record
+    Delta,
+    /// Time since the last record with a message matching the pattern
+    Marker(Regex),
+}
+
+/// Time mode from args. None if timestamps are absolute.
+pub fn from_args(args: &ArgMatches) -> Result<Option<Mode>, Error> {
+    Ok(match args.value_of("time") {
+        None | Some("absolute") => None,
+        Some("relative") => Some(Mode::Relative),
+        Some("delta") => Some(Mode::Delta),
+        Some("marker") => {
+            let pattern = args
+                .value_of("time-marker")
+                .ok_or_else(|| format_err!("Missing time marker pattern"))?;
+            let pattern = Regex::new(pattern)
+                .map_err(|e| format_err!("Invalid time marker \"{}\": {}", pattern, e))?;
+            Some(Mode::Marker(pattern))
+        }
+        Some(mode) => return Err(format_err!("Invalid time mode {}", mode)),
+    })
+}
+
+/// Gaps between records longer than this are flagged
+pub fn gap_from_args(args: &ArgMatches) -> Result<Option<Duration>, Error> {
+    match args.value_of("time-gap") {
+        Some(gap) => parse_duration(gap).map(Some),
+        None if args.value_of("time").unwrap_or("absolute") != "absolute" => Ok(Some(DEFAULT_GAP)),
+        None => Ok(None),
+    }
+}
+
+/// Seconds from `from` to `to`
+fn seconds(from: &Tm, to: &Tm) -> f64 {
+    let delta = *to - *from;
+    delta
+        .num_microseconds()
+        .map(|us| us as f64 / 1_000_000.0)
+        .unwrap_or_else(|| delta.num_seconds() as f64)
+}
+
+/// Set the elapsed time of records relative to the reference of the mode and
+/// flag gaps. Runs before the filters: delta and gaps are measured to the
+/// previous record of the input whether it is shown or not and markers do not
+/// need to pass the filters.
+pub struct Elapsed<S> {
+    inner: S,
+    mode: Option<Mode>,
+    /// Flag records after gaps longer than this
+    gap: Option<time::Duration>,
+    /// Timestamp the elapsed time is measured from
+    reference: Option<Tm>,
+    /// Timestamp of the previous record
+    previous: Option<Tm>,
+}
+
+impl<S> Elapsed<S> {
+    pub fn new(inner: S, mode: Option<Mode>, gap: Option<Duration>) -> Elapsed<S> {
+        Elapsed {
+            inner,
+            mode,
+            gap: gap.and_then(|g| time::Duration::from_std(g).ok()),
+            reference: None,
+            previous: None,
+        }
+    }
+
+    fn process(&mut self, record: &mut Record) {
+        let timestamp = match record.timestamp {
+            Some(ref timestamp) => timestamp.tm,
+            None => return,
+        };
+        if let (Some(gap), Some(previous)) = (self.gap, self.previous) {
+            record.gap = timestamp - previous > gap;
+        }
+        self.previous = Some(timestamp);
+
+        let reference = match self.mode {
+            None => return,
+            Some(Mode::Relative) => *self.reference.get_or_insert(timestamp),
+            Some(Mode::Delta) => self.reference.replace(timestamp).unwrap_or(timestamp),
+            Some(Mode::Marker(ref pattern)) => {
+                if pattern.is_match(&record.message) {
+                    self.reference = Some(timestamp);
+                }
+                match self.reference {
+                    Some(reference) => reference,
+                    // No marker seen yet
+                    None => return,
+                }
+            }
+        };
+        record.elapsed = Some(seconds(&reference, &timestamp));
+    }
+}
+
+impl<S> Stream for Elapsed<S>
+where
+    S: Stream<Item = Record, Error = Error>,
+{
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match try_ready!(self.inner.poll()) {
+            Some(mut record) => {
+                self.process(&mut record);
+                Ok(Async::Ready(Some(record)))
+            }
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+#[test]
+fn test_elapsed() {
+    use rogcat::record::Timestamp;
+
+    let record = |t: &str, message: &str| Record {
+        timestamp: Some(Timestamp::new(
+            time::strptime(t, "%m-%d %H:%M:%S.%f").unwrap(),
+        )),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    let records = [
+        record("01-01 00:00:01.000", "a"),
+        record("01-01 00:00:01.250", "start"),
+        Record::default(),
+        record("01-01 00:00:03.000", "b"),
+    ];
+    let elapsed = |mode| {
+        let mut elapsed = Elapsed::new((), Some(mode), None);
+        records
+            .iter()
+            .cloned()
+            .map(|mut r| {
+                elapsed.process(&mut r);
+                r.elapsed
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        elapsed(Mode::Relative),
+        vec![Some(0.0), Some(0.25), None, Some(2.0)]
+    );
+    assert_eq!(
+        elapsed(Mode::Delta),
+        vec![Some(0.0), Some(0.25), None, Some(1.75)]
+    );
+    assert_eq!(
+        elapsed(Mode::Marker(Regex::new("^start$").unwrap())),
+        vec![None, Some(0.0), None, Some(1.75)]
+    );
+}
+
+#[test]
+fn test_gap() {
+    use rogcat::record::Timestamp;
+
+    let record = |t: &str| Record {
+        timestamp: Some(Timestamp::new(
+            time::strptime(t, "%m-%d %H:%M:%S.%f").unwrap(),
+        )),
+        ..Default::default()
+    };
+    let mut elapsed = Elapsed::new((), None, Some(Duration::from_secs(1)));
+    let gaps = [
+        "01-01 00:00:01.000",
+        "01-01 00:00:01.500",
+        "01-01 00:00:03.000",
+        "01-01 00:00:03.100",
+    ]
+    .iter()
+    .map(|t| {
+        let mut r = record(t);
+        elapsed.process(&mut r);
+        assert_eq!(r.elapsed, None);
+        r.gap
+    })
+    .collect::<Vec<_>>();
+    assert_eq!(gaps, vec![false, false, true, false]);
+}
@@ -18,7 +18,10 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::LogSink;
+use crate::{
+    highlight::{Highlight, Rule},
+    sqlite, LogSink,
+};
 use clap::ArgMatches;
 use failure::{err_msg, format_err, Error};
 use futures::{Async, AsyncSink, Poll, Sink, StartSend};
@@ -54,12 +57,17 @@ struct FileWriter<T> {
     filename_format: FilenameFormat,
     index: usize,
     format: Format,
+    highlight: Highlight,
     progress: ProgressBar,
     writer: Option<Box<T>>,
 }
 
 trait Writer {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Self, Error>
+    fn with_file_format(
+        filename: &Path,
+        format: &Format,
+        highlight: &Highlight,
+    ) -> Result<Self, Error>
     where
         Self: Sized;
     fn write(&mut self, record: &Record, index: usize) -> Result<(), Error>;
@@ -68,24 +76,64 @@ trait Writer {
     }
 }
 
+/// Render `records` into a static html page with highlighted messages
+pub fn write_html<W: Write>(records: Vec<Record>, highlight: &[Rule], out: W) -> Result<(), Error> {
+    let records = records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| html::HtmlRecord { index, record })
+        .collect::<Vec<_>>();
+    html::render(&records, highlight, out)
+}
+
 /// Crate a new log sink for given arguments
-pub fn try_from(args: &ArgMatches) -> Result<LogSink, Error> {
-    let format = args
-        .value_of("format")
-        .and_then(|f| Format::from_str(f).ok())
-        .unwrap_or(Format::Raw);
+pub fn try_from(args: &ArgMatches, highlight: Highlight) -> Result<LogSink, Error> {
+    let filename = Path::new(args.value_of("output").unwrap_or_default());
+    let format = match args.value_of("format") {
+        Some(format) => Format::from_str(format).map_err(err_msg)?,
+        None if filename
+            .extension()
+            .map(|e| e == "db" || e == "sqlite")
+            .unwrap_or(false) =>
+        {
+            Format::Sqlite
+        }
+        None => Format::Raw,
+    };
+    if args.is_present("highlight") && format != Format::Html {
+        return Err(format_err!(
+            "--highlight is only supported with terminal or html output"
+        ));
+    }
 
     Ok(match format {
         Format::Csv | Format::Json | Format::Raw => {
-            Box::new(FileWriter::<Textfile>::from_args(args, format)?) as LogSink
+            Box::new(FileWriter::<Textfile>::from_args(args, format, highlight)?) as LogSink
         }
-        Format::Html => Box::new(FileWriter::<html::Html>::from_args(args, format)?) as LogSink,
+        Format::Html => Box::new(FileWriter::<html::Html>::from_args(
+            args, format, highlight,
+        )?) as LogSink,
         Format::Human => panic!("Unsupported format human in output file"),
+        Format::Sqlite => {
+            for arg in &["records-per-file", "filename-format"] {
+                if args.is_present(arg) {
+                    return Err(format_err!("--{} is not supported with sqlite output", arg));
+                }
+            }
+            Box::new(sqlite::Database::create(
+                filename,
+                args.is_present("overwrite"),
+            )?) as LogSink
+        }
     })
 }
 
 impl Writer for Textfile {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Textfile, Error> {
+    fn with_file_format(
+        filename: &Path,
+        format: &Format,
+        _: &Highlight,
+    ) -> Result<Textfile, Error> {
         let file = File::create(filename).map_err(|e| {
             format_err!("Failed to create output file {}: {}", filename.display(), e)
         })?;
@@ -107,7 +155,11 @@ impl Writer for Textfile {
 }
 
 impl<'a, T: Writer> FileWriter<T> {
-    pub fn from_args(args: &ArgMatches<'a>, format: Format) -> Result<Self, Error> {
+    pub fn from_args(
+        args: &ArgMatches<'a>,
+        format: Format,
+        highlight: Highlight,
+    ) -> Result<Self, Error> {
         let filename = args
             .value_of("output")
             .map(PathBuf::from)
@@ -178,6 +230,7 @@ impl<'a, T: Writer> FileWriter<T> {
             filename_format,
             index: 0,
             format,
+            highlight,
             progress,
             writer: None,
         })
@@ -284,7 +337,8 @@ impl<'a, T: Writer> FileWriter<T> {
             }
             None => {
                 self.current_filename = self.next_file()?;
-                let mut writer = T::with_file_format(&self.current_filename, &self.format)?;
+                let mut writer =
+                    T::with_file_format(&self.current_filename, &self.format, &self.highlight)?;
                 let message = format!("Writing {}", self.current_filename.display());
                 self.progress.set_message(&message);
                 writer.write(record, self.index)?;
@@ -337,30 +391,80 @@ impl<T: Writer> Sink for FileWriter<T> {
 
 mod html {
     use super::Writer;
+    use crate::highlight::{self, Highlight, Rule};
     use crc::{crc32, Hasher32};
     use failure::{format_err, Error};
     use handlebars::{
-        to_json, Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext,
-        RenderError,
+        html_escape, to_json, Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender,
+        Output, RenderContext, RenderError,
     };
     use rogcat::record::{Format, Record};
     use serde::Serialize;
     use serde_json::value::{Map, Value as Json};
     use std::{
         fs::File,
+        io::Write,
         path::{Path, PathBuf},
         str,
     };
 
     #[derive(Serialize)]
-    struct HtmlRecord {
-        index: usize,
-        record: Record,
+    pub(super) struct HtmlRecord {
+        pub(super) index: usize,
+        pub(super) record: Record,
+    }
+
+    /// Escapes a message and wraps the spans that match highlight rules
+    struct Highlighter(Vec<Rule>);
+
+    impl HelperDef for Highlighter {
+        fn call<'reg: 'rc, 'rc>(
+            &self,
+            h: &Helper<'reg, 'rc>,
+            _: &'reg Handlebars,
+            _: &'rc Context,
+            _: &mut RenderContext<'reg>,
+            out: &mut dyn Output,
+        ) -> HelperResult {
+            let param = h
+                .param(0)
+                .ok_or_else(|| RenderError::new("Param 0 is required for highlight helper."))?;
+            let value = param.value().render();
+            for (text, style) in highlight::segments(&self.0, &value) {
+                match style {
+                    Some(style) => out.write(&format!(
+                        "<span style=\"{}\">{}</span>",
+                        style.css(),
+                        html_escape(&text)
+                    ))?,
+                    None => out.write(&html_escape(&text))?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Render `records` with the html template to `out`
+    pub(super) fn render<W: Write>(
+        records: &[HtmlRecord],
+        highlight: &[Rule],
+        mut out: W,
+    ) -> Result<(), Error> {
+        let mut hb = Handlebars::new();
+        let mut data: Map<String, Json> = Map::new();
+        data.insert("records".to_owned(), to_json(records));
+        hb.register_helper("color", Box::new(Html::color_helper));
+        hb.register_helper("highlight", Box::new(Highlighter(highlight.to_vec())));
+        hb.register_template_string("t1", HTML_TEMPLATE)?;
+        hb.render_to_write("t1", &data, &mut out)
+            .map_err(|e| format_err!("Rednering error: {}", e))
+            .map(|_| ())
     }
 
     /// Simple static html file
     pub struct Html {
         filename: PathBuf,
+        highlight: Highlight,
         records: Vec<HtmlRecord>,
     }
 
@@ -400,22 +504,20 @@ mod html {
         }
 
         fn flush(&mut self) -> Result<(), Error> {
-            let mut hb = Handlebars::new();
-            let mut data: Map<String, Json> = Map::new();
-            data.insert("records".to_owned(), to_json(&self.records));
-            let mut output_file = File::create(&self.filename)?;
-            hb.register_helper("color", Box::new(Self::color_helper));
-            hb.register_template_string("t1", HTML_TEMPLATE)?;
-            hb.render_to_write("t1", &data, &mut output_file)
-                .map_err(|e| format_err!("Rednering error: {}", e))
-                .map(|_| ())
+            let highlight = self.highlight.lock().expect("Failed to lock highlight");
+            render(&self.records, &highlight, File::create(&self.filename)?)
         }
     }
 
     impl Writer for Html {
-        fn with_file_format(filename: &Path, _: &Format) -> Result<Html, Error> {
+        fn with_file_format(
+            filename: &Path,
+            _: &Format,
+            highlight: &Highlight,
+        ) -> Result<Html, Error> {
             Ok(Html {
                 filename: filename.to_owned(),
+                highlight: highlight.clone(),
                 records: Vec::new(),
             })
         }
@@ -510,7 +612,7 @@ table tr td:first-child + td + td + td + td + td {
     <td>{{color t.record.process}}</td>
     <td>{{color t.record.thread}}</td>
     <td class="level-{{t.record.level}}">{{t.record.level}}</td>
-    <td>{{t.record.message}}</td>
+    <td>{{highlight t.record.message}}</td>
     </tr>
 {{/each~}}
 
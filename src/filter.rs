@@ -18,9 +18,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::profiles::Profile;
+use crate::{cli, profiles::Profile};
 use clap::ArgMatches;
-use failure::Error;
+use failure::{err_msg, format_err, Error};
 use regex::{RegexSet, RegexSetBuilder};
 use rogcat::record::{Level, Record};
 
@@ -41,7 +41,11 @@ pub struct Filter {
 pub fn from_args_profile(args: &ArgMatches, profile: &Profile) -> Result<Filter, Error> {
     // Level is filtered by ffx in case of fuchsia.
     let level = (!args.is_present("fuchsia"))
-        .then(|| args.value_of("level").map(Level::from))
+        .then(|| {
+            args.value_of("level")
+                .or(profile.options.level.as_deref())
+                .map(Level::from)
+        })
         .flatten();
 
     let filter = args
@@ -116,6 +120,19 @@ pub fn from_args_profile(args: &ArgMatches, profile: &Profile) -> Result<Filter,
     Ok(filter)
 }
 
+/// Parse a filter line with the filter options of rogcat e.g
+/// `-t ActivityManager -l warn`. An empty line is no filter.
+pub fn parse(line: &str) -> Result<Option<Filter>, Error> {
+    let args = shell_words::split(line).map_err(|e| format_err!("{}", e))?;
+    if args.is_empty() {
+        return Ok(None);
+    }
+    let matches = cli::client_filter()
+        .get_matches_from_safe(args)
+        .map_err(|e| err_msg(e.message.lines().next().unwrap_or_default().to_owned()))?;
+    from_args_profile(&matches, &Profile::default()).map(Some)
+}
+
 impl Filter {
     pub fn filter(&self, record: &Record) -> bool {
         if let Some(ref level) = self.level {
@@ -220,3 +237,19 @@ impl FilterSet {
         !self.negative.is_empty() && iter.any(|i| self.negative.is_match(i.as_ref()))
     }
 }
+
+#[test]
+fn test_parse() {
+    let record = Record {
+        tags: vec!["ActivityManager".into()],
+        level: Level::Warn,
+        message: "foo".into(),
+        ..Default::default()
+    };
+    assert!(parse("").unwrap().is_none());
+    let filter = parse("-t ActivityManager -l W").unwrap().unwrap();
+    assert!(filter.filter(&record));
+    let filter = parse("-m 'bar baz'").unwrap().unwrap();
+    assert!(!filter.filter(&record));
+    assert!(parse("--foo").is_err());
+}
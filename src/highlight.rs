@@ -0,0 +1,287 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::profiles::Profile;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use regex::Regex;
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use termcolor::{Color, ColorSpec};
+
+/// Style of highlights without an explicit style
+const DEFAULT_STYLE: &str = "bold yellow";
+
+/// Highlight rules that can be replaced while a sink is in use
+pub type Highlight = Arc<Mutex<Vec<Rule>>>;
+
+/// Colors and attributes of a highlighted span e.g `bold red on white`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+}
+
+impl FromStr for Style {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Style, Error> {
+        fn color(s: &str) -> Result<Color, Error> {
+            match s.strip_prefix('#') {
+                Some(hex) if hex.len() == 6 => {
+                    let c = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+                    match (c(0), c(2), c(4)) {
+                        (Ok(r), Ok(g), Ok(b)) => Ok(Color::Rgb(r, g, b)),
+                        _ => Err(format_err!("Invalid color \"{}\"", s)),
+                    }
+                }
+                _ => Color::from_str(s).map_err(|_| format_err!("Invalid color \"{}\"", s)),
+            }
+        }
+
+        let mut style = Style::default();
+        let mut words = s.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "bold" => style.bold = true,
+                "underline" => style.underline = true,
+                "on" => {
+                    let bg = words
+                        .next()
+                        .ok_or_else(|| format_err!("Invalid style \"{}\": missing color", s))?;
+                    style.bg = Some(color(bg)?);
+                }
+                fg => style.fg = Some(color(fg)?),
+            }
+        }
+        if style == Style::default() {
+            return Err(format_err!("Invalid style \"{}\"", s));
+        }
+        Ok(style)
+    }
+}
+
+impl Style {
+    /// Stack `other` on top of this style. Colors of `other` win.
+    fn stack(&mut self, other: &Style) {
+        self.fg = other.fg.or(self.fg);
+        self.bg = other.bg.or(self.bg);
+        self.bold |= other.bold;
+        self.underline |= other.underline;
+    }
+
+    /// Apply the style to `spec`
+    pub fn apply(&self, spec: &mut ColorSpec) {
+        if self.fg.is_some() {
+            spec.set_fg(self.fg);
+        }
+        if self.bg.is_some() {
+            spec.set_bg(self.bg);
+        }
+        spec.set_bold(self.bold).set_underline(self.underline);
+    }
+
+    /// Inline css of the style
+    pub fn css(&self) -> String {
+        let mut css = Vec::new();
+        if let Some(fg) = self.fg {
+            css.push(format!("color:{}", css_color(fg)));
+        }
+        if let Some(bg) = self.bg {
+            css.push(format!("background:{}", css_color(bg)));
+        }
+        if self.bold {
+            css.push("font-weight:bold".to_owned());
+        }
+        if self.underline {
+            css.push("text-decoration:underline".to_owned());
+        }
+        css.join(";")
+    }
+}
+
+/// Css color of `color` matching the palette of the html output
+fn css_color(color: Color) -> String {
+    const PALETTE: [&str; 8] = [
+        "#4F4F4F", "#FF6C60", "#A8FF60", "#FFFFB6", "#96CBFE", "#FF73FD", "#C6C5FE", "#EEEEEE",
+    ];
+    let (r, g, b) = match color {
+        Color::Black => return PALETTE[0].to_owned(),
+        Color::Red => return PALETTE[1].to_owned(),
+        Color::Green => return PALETTE[2].to_owned(),
+        Color::Yellow => return PALETTE[3].to_owned(),
+        Color::Blue => return PALETTE[4].to_owned(),
+        Color::Magenta => return PALETTE[5].to_owned(),
+        Color::Cyan => return PALETTE[6].to_owned(),
+        Color::White => return PALETTE[7].to_owned(),
+        Color::Ansi256(n) if n < 16 => return PALETTE[n as usize % 8].to_owned(),
+        Color::Ansi256(n) if n >= 232 => {
+            let gray = 8 + 10 * (n - 232);
+            (gray, gray, gray)
+        }
+        Color::Ansi256(n) => {
+            let level = |c: u8| if c == 0 { 0 } else { 55 + 40 * c };
+            let n = n - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        Color::Rgb(r, g, b) => (r, g, b),
+        _ => return "inherit".to_owned(),
+    };
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Pattern and style e.g `FATAL => bold red`
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub pattern: Regex,
+    pub style: Style,
+}
+
+impl Rule {
+    /// Parse a rule of the form `PATTERN` or `PATTERN => STYLE`. Only a `=>`
+    /// surrounded by whitespace separates the style.
+    pub fn parse(spec: &str) -> Result<Rule, Error> {
+        let separator = spec.rmatch_indices("=>").map(|(i, _)| i).find(|&i| {
+            spec[..i].ends_with(char::is_whitespace)
+                && spec[i + 2..].starts_with(char::is_whitespace)
+        });
+        let (pattern, style) = match separator {
+            Some(i) => (spec[..i].trim(), &spec[i + 2..]),
+            None => (spec, DEFAULT_STYLE),
+        };
+        let pattern = Regex::new(pattern).map_err(|e| {
+            let e = e.to_string();
+            let e = e.lines().last().unwrap_or_default().to_owned();
+            format_err!("Invalid highlight \"{}\": {}", spec, e)
+        })?;
+        let style = style
+            .parse()
+            .map_err(|e| format_err!("Invalid highlight \"{}\": {}", spec, e))?;
+        Ok(Rule { pattern, style })
+    }
+}
+
+/// Highlight rules from args and profile
+pub fn from_args_profile(args: &ArgMatches, profile: &Profile) -> Result<Vec<Rule>, Error> {
+    profile
+        .highlight
+        .iter()
+        .map(String::as_str)
+        .chain(args.values_of("highlight").unwrap_or_default())
+        .map(Rule::parse)
+        .collect()
+}
+
+/// Style of each character of `text`. Styles of overlapping matches stack
+/// with later rules on top.
+pub fn styles(rules: &[Rule], text: &str) -> Vec<Option<Style>> {
+    let mut styles = vec![None; text.len()];
+    for rule in rules {
+        for m in rule.pattern.find_iter(text) {
+            for style in &mut styles[m.start()..m.end()] {
+                style.get_or_insert_with(Style::default).stack(&rule.style);
+            }
+        }
+    }
+    text.char_indices().map(|(i, _)| styles[i]).collect()
+}
+
+/// Split `text` into runs of characters with the same style
+pub fn segments(rules: &[Rule], text: &str) -> Vec<(String, Option<Style>)> {
+    let mut segments: Vec<(String, Option<Style>)> = Vec::new();
+    for (c, style) in text.chars().zip(styles(rules, text)) {
+        match segments.last_mut() {
+            Some((s, last)) if *last == style => s.push(c),
+            _ => segments.push((c.to_string(), style)),
+        }
+    }
+    segments
+}
+
+#[test]
+fn test_style() {
+    let style = |s: &str| Style::from_str(s).unwrap();
+    assert_eq!(
+        style("bold red"),
+        Style {
+            fg: Some(Color::Red),
+            bold: true,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        style("underline 208 on #102030"),
+        Style {
+            fg: Some(Color::Ansi256(208)),
+            bg: Some(Color::Rgb(0x10, 0x20, 0x30)),
+            underline: true,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        style("bold on blue").css(),
+        "background:#96CBFE;font-weight:bold"
+    );
+    assert!(Style::from_str("").is_err());
+    assert!(Style::from_str("bold on").is_err());
+    assert!(Style::from_str("purple").is_err());
+    assert!(Rule::parse("(").is_err());
+    assert_eq!(
+        Rule::parse("a=>b => bold").unwrap().pattern.as_str(),
+        "a=>b"
+    );
+    let rule = Rule::parse("a=>b").unwrap();
+    assert_eq!(rule.pattern.as_str(), "a=>b");
+    assert_eq!(rule.style, style(DEFAULT_STYLE));
+    assert!(Rule::parse("a => bold=>b").is_err());
+}
+
+#[test]
+fn test_segments() {
+    let rules = ["FATAL => bold red", "AL E => underline blue"]
+        .iter()
+        .map(|r| Rule::parse(r).unwrap())
+        .collect::<Vec<_>>();
+    let red = Style {
+        fg: Some(Color::Red),
+        bold: true,
+        ..Default::default()
+    };
+    let blue = Style {
+        fg: Some(Color::Blue),
+        underline: true,
+        ..Default::default()
+    };
+    let stacked = Style { bold: true, ..blue };
+    assert_eq!(
+        segments(&rules, "ä FATAL ERROR"),
+        vec![
+            ("ä ".to_owned(), None),
+            ("FAT".to_owned(), Some(red)),
+            ("AL".to_owned(), Some(stacked)),
+            (" E".to_owned(), Some(blue)),
+            ("RROR".to_owned(), None),
+        ]
+    );
+}
@@ -18,6 +18,6 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-#[allow(clippy::double_comparisons)]
+#[allow(clippy::double_comparisons, non_local_definitions)]
 pub mod parser;
 pub mod record;
@@ -20,20 +20,48 @@
 
 use failure::Error;
 use futures::{sync::oneshot, Future, Sink, Stream};
-use rogcat::{parser, record::Record};
-use std::{env, process::exit, str::FromStr};
+use rogcat::{
+    parser,
+    record::{Output, Record},
+};
+use std::{
+    env, io,
+    path::PathBuf,
+    process::exit,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    },
+};
 use tokio::runtime::Runtime;
 use tokio_signal::ctrl_c;
 use url::Url;
 
+mod adb;
+mod bugreport;
 mod cli;
+mod convert;
+mod crashes;
+mod diff;
+mod elapsed;
 mod filewriter;
 mod filter;
+mod highlight;
+#[allow(clippy::io_other_error)]
 mod lossy_lines;
+mod pager;
 mod profiles;
 mod reader;
+mod reload;
+mod replay;
+mod server;
+mod sqlite;
+mod stats;
 mod subcommands;
 mod terminal;
+mod theme;
+mod trigger;
 mod utils;
 
 const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
@@ -42,16 +70,38 @@ const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
 pub enum StreamData {
     Record(Record),
     Line(String),
+    /// Line read from stdout or stderr of a spawned command
+    Output(Output, String),
+    /// Exit code of a spawned command and a record reporting it
+    Exit(i32, Record),
+}
+
+impl StreamData {
+    /// Parse lines into records and tag the output stream of spawned commands
+    pub fn into_record(self, parser: &mut parser::Parser) -> Record {
+        match self {
+            StreamData::Line(line) => parser.parse(line),
+            StreamData::Output(output, line) => Record {
+                output: Some(output),
+                ..parser.parse(line)
+            },
+            StreamData::Record(record) | StreamData::Exit(_, record) => record,
+        }
+    }
 }
 
 type LogStream = Box<dyn Stream<Item = StreamData, Error = Error> + Send>;
 type LogSink = Box<dyn Sink<SinkItem = Record, SinkError = Error> + Send>;
+type RecordStream = Box<dyn Stream<Item = Record, Error = Error> + Send>;
 
 fn run() -> Result<(), Error> {
     let args = cli::cli().get_matches();
     utils::config_init();
     subcommands::run(&args);
 
+    let profile = profiles::from_args(&args)?;
+    let cli_args = args.clone();
+    let args = profiles::apply(args, &profile)?;
     let source = {
         if args.is_present("input") {
             reader::files(&args)?
@@ -66,12 +116,14 @@ fn run() -> Result<(), Error> {
                         match url.scheme() {
                             #[cfg(target_os = "linux")]
                             "can" => reader::can(url.host_str().expect("Invalid can device"))?,
-                            "tcp" => reader::tcp(&url)?,
+                            "tcp" => reader::tcp(&url, args.value_of("remote-filter"))?,
+                            #[cfg(unix)]
+                            "unix" => reader::unix(&url, args.value_of("remote-filter"))?,
                             "serial" => reader::serial(&args),
-                            _ => reader::process(&args)?,
+                            _ => reader::process(&args, &profile)?,
                         }
                     } else {
-                        reader::process(&args)?
+                        reader::process(&args, &profile)?
                     }
                 }
                 None => reader::logcat(&args)?,
@@ -79,11 +131,13 @@ fn run() -> Result<(), Error> {
         }
     };
 
-    let profile = profiles::from_args(&args)?;
+    let highlight = highlight::from_args_profile(&args, &profile)?;
+    pager::from_args(&args, &highlight)?;
+    let highlight = Arc::new(Mutex::new(highlight));
     let sink = if args.is_present("output") {
-        filewriter::try_from(&args)?
+        filewriter::try_from(&args, highlight.clone())?
     } else {
-        terminal::try_from(&args, &profile)?
+        terminal::try_from(&args, highlight.clone())?
     };
 
     // Stop process after n records if argument head is passed
@@ -92,16 +146,60 @@ fn run() -> Result<(), Error> {
         .map(|v| usize::from_str(v).expect("Invalid head arguement"));
 
     let filter = filter::from_args_profile(&args, &profile)?;
+    let triggers = trigger::from_args_profile(&args, &profile)?;
     let mut parser = parser::Parser::default();
 
+    // Optionally write crash reports of all records before filtering
+    let crash_reports = args
+        .value_of("crash-reports")
+        .map(|d| crashes::Reports::new(PathBuf::from(d), crashes::DEFAULT_CONTEXT))
+        .map(|r| Arc::new(Mutex::new(r)));
+    let crash_reports_detect = crash_reports.clone();
+    let crash_reports_finish = crash_reports.clone();
+    let crash_reports_error = crash_reports.clone();
+
+    // Optionally exit with the exit code of the command
+    let exit_code = Arc::new(AtomicI32::new(0));
+    let exit_code_store = exit_code.clone();
+    let mirror_exit_code = args.is_present("exit-code");
+
     let mut runtime = Runtime::new()?;
 
-    let f = source
-        .map(move |a| match a {
-            StreamData::Line(line) => parser.parse(line),
-            StreamData::Record(record) => record,
-        })
-        .filter(move |r| filter.filter(r))
+    let mut records: RecordStream = Box::new(source.map(move |a| {
+        if let StreamData::Exit(code, _) = a {
+            exit_code_store.store(code, Ordering::Relaxed);
+        }
+        a.into_record(&mut parser)
+    }));
+    if let Some(config) = replay::from_args(&args)? {
+        records = Box::new(replay::Replay::new(records, config));
+    }
+
+    let mode = elapsed::from_args(&args)?;
+    let gap = elapsed::gap_from_args(&args)?;
+    if mode.is_some() || gap.is_some() {
+        records = Box::new(elapsed::Elapsed::new(records, mode, gap));
+    }
+
+    let records = records.and_then(move |r| {
+        if let Some(ref reports) = crash_reports_detect {
+            reports.lock().expect("Failed to lock").process(&r)?;
+        }
+        Ok(r)
+    });
+    // Filter records and stop if a trigger exits
+    let records = trigger::Triggers::new(
+        records,
+        filter,
+        triggers,
+        args.value_of("dev").map(ToOwned::to_owned),
+    );
+    let trigger_exit_code = records.exit_code();
+    // Rebuild filter and highlights when the profiles change
+    let filter = records.shared_filter();
+    let records = reload::Reload::new(records, cli_args, filter, highlight);
+
+    let f = records
         .take_while(move |_| {
             Ok(match head {
                 Some(0) => false,
@@ -113,9 +211,36 @@ fn run() -> Result<(), Error> {
             })
         })
         .forward(sink)
-        .map(|_| exit(0))
-        .map_err(|e| eprintln!("{e}"));
+        .map(move |(_, sink)| {
+            // Flush file sinks e.g html before exiting
+            drop(sink);
+            pager::finish();
+            finish_crash_reports(&crash_reports_finish);
+            if let Some(code) = *trigger_exit_code.lock().expect("Failed to lock") {
+                exit(code)
+            }
+            if mirror_exit_code {
+                exit(exit_code.load(Ordering::Relaxed))
+            }
+            exit(0)
+        })
+        .map_err(move |e| {
+            // Exit instead of waiting for ctrl-c e.g if the terminal is gone
+            // after a SIGHUP that is handled as reload
+            finish_crash_reports(&crash_reports_error);
+            // Writing fails if the pager was quit before the end of the output
+            let quit = pager::quit();
+            pager::finish();
+            if quit {
+                exit(0)
+            }
+            eprintln!("{e}");
+            exit(1)
+        });
     let mut f = Some(oneshot::spawn(f, &runtime.executor()));
+    if let Some(ref reports) = crash_reports {
+        runtime.spawn(crashes::expire(reports.clone()));
+    }
 
     // Cancel stream processing on ctrl-c
     runtime.block_on(ctrl_c().flatten_stream().take(1).for_each(move |()| {
@@ -123,11 +248,27 @@ fn run() -> Result<(), Error> {
         Ok(())
     }))?;
 
+    finish_crash_reports(&crash_reports);
+
     Ok(())
 }
 
+/// Write reports of crashes that are still collecting records and print
+/// the summary to stderr to keep it apart from the records
+fn finish_crash_reports(reports: &Option<Arc<Mutex<crashes::Reports>>>) {
+    if let Some(reports) = reports {
+        let mut reports = reports.lock().expect("Failed to lock");
+        if let Err(e) = reports.finish() {
+            eprintln!("{e}");
+        }
+        reports.summary.print(&mut io::stderr()).ok();
+    }
+}
+
 fn main() {
-    match run() {
+    let result = run();
+    pager::finish();
+    match result {
         Err(e) => {
             eprintln!("{e}");
             exit(1)
@@ -0,0 +1,192 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{highlight::Rule, utils::config_get};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use lazy_static::lazy_static;
+use std::{
+    env,
+    ffi::OsStr,
+    io::{stdout, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+};
+
+/// Pager used if neither ROGCAT_PAGER, the config nor PAGER set one
+const DEFAULT_PAGER: &str = "less -R";
+/// Options of less if LESS is unset. Quit if the output fits on one screen,
+/// pass colors and don't clear the screen.
+const DEFAULT_LESS: &str = "FRX";
+
+lazy_static! {
+    static ref PAGER: Mutex<Option<Pager>> = Mutex::new(None);
+}
+
+/// Running pager and the original stdout and stderr
+struct Pager {
+    child: Child,
+    #[cfg(unix)]
+    fds: Vec<(i32, i32)>,
+}
+
+/// Pager command from ROGCAT_PAGER, the config, PAGER or the default. An
+/// empty command or `cat` disables the pager.
+fn command() -> Option<String> {
+    let command = env::var("ROGCAT_PAGER")
+        .ok()
+        .or_else(|| config_get("pager"))
+        .or_else(|| env::var("PAGER").ok())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_owned());
+    match command.trim() {
+        "" | "cat" => None,
+        command => Some(command.to_owned()),
+    }
+}
+
+/// Arguments that make less search for the highlighted patterns
+fn search(command: &str, rules: &[Rule]) -> Vec<String> {
+    let program = shell_words::split(command)
+        .ok()
+        .and_then(|words| words.into_iter().next())
+        .unwrap_or_default();
+    let less = Path::new(&program).file_name() == Some(OsStr::new("less"));
+    if rules.is_empty() || !less {
+        return Vec::new();
+    }
+    let pattern = rules
+        .iter()
+        .map(|r| format!("({})", r.pattern.as_str()))
+        .collect::<Vec<_>>()
+        .join("|");
+    vec!["-p".to_owned(), pattern]
+}
+
+/// Start the pager and redirect stdout and stderr if they are terminals into
+/// it. The terminal output is paged unless `--no-pager` is passed if the
+/// output is finite e.g `--dump` or `--input` or if `--pager` is passed.
+pub fn from_args(args: &ArgMatches, rules: &[Rule]) -> Result<(), Error> {
+    let finite = ["dump", "last", "tail", "input"]
+        .iter()
+        .any(|a| args.is_present(a));
+    if args.is_present("no-pager")
+        || args.is_present("output")
+        || !(finite || args.is_present("pager"))
+        || !atty::is(atty::Stream::Stdout)
+    {
+        return Ok(());
+    }
+    match command() {
+        Some(command) => start(&command, &search(&command, rules)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn start(command: &str, arguments: &[String]) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+    use tokio_signal::unix::libc;
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$@\""))
+        .arg(command)
+        .args(arguments)
+        .env(
+            "LESS",
+            env::var("LESS").unwrap_or_else(|_| DEFAULT_LESS.into()),
+        )
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format_err!("Failed to run pager \"{}\": {}", command, e))?;
+    let pipe = child
+        .stdin
+        .take()
+        .ok_or_else(|| format_err!("Failed to open pager \"{}\"", command))?;
+
+    let mut fds = vec![1];
+    if atty::is(atty::Stream::Stderr) {
+        fds.push(2);
+    }
+    let fds = fds
+        .into_iter()
+        .map(|fd| unsafe {
+            let original = libc::dup(fd);
+            libc::dup2(pipe.as_raw_fd(), fd);
+            (fd, original)
+        })
+        .collect();
+
+    *PAGER.lock().expect("Failed to lock pager") = Some(Pager { child, fds });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn start(_command: &str, _arguments: &[String]) -> Result<(), Error> {
+    Ok(())
+}
+
+/// True if the output goes to a pager
+pub fn is_active() -> bool {
+    PAGER.lock().map(|p| p.is_some()).unwrap_or(false)
+}
+
+/// True if the user quit the pager before the output ended
+pub fn quit() -> bool {
+    PAGER
+        .lock()
+        .ok()
+        .and_then(|mut p| p.as_mut().map(|p| p.child.try_wait()))
+        .map(|status| matches!(status, Ok(Some(_))))
+        .unwrap_or(false)
+}
+
+/// Close the output to the pager and wait until the user quits it
+pub fn finish() {
+    let pager = PAGER.lock().ok().and_then(|mut p| p.take());
+    if let Some(mut pager) = pager {
+        stdout().flush().ok();
+        #[cfg(unix)]
+        for (fd, original) in pager.fds {
+            use tokio_signal::unix::libc;
+            unsafe {
+                libc::dup2(original, fd);
+                libc::close(original);
+            }
+        }
+        pager.child.wait().ok();
+    }
+}
+
+#[test]
+fn test_search() {
+    let rules = ["ANR", "Fatal.*"]
+        .iter()
+        .map(|r| Rule::parse(r).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        search("less -R", &rules),
+        vec!["-p".to_owned(), "(ANR)|(Fatal.*)".to_owned()]
+    );
+    assert_eq!(search("/usr/bin/less", &rules[..1])[1], "(ANR)");
+    assert!(search("less -R", &[]).is_empty());
+    assert!(search("most", &rules).is_empty());
+}
@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::record::{Level, Record, Timestamp};
+use crate::record::{CsvRecord, Format, Level, Record, Timestamp};
 use csv::ReaderBuilder;
 use failure::Fail;
 
@@ -26,7 +26,7 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take, take_till1, take_until, take_until1, take_while_m_n},
     character::{
-        complete::{char, hex_digit1, i32, space0, space1},
+        complete::{char, digit1, hex_digit1, i32, space0, space1},
         is_digit,
     },
     combinator::{map, opt, peek, rest},
@@ -35,7 +35,6 @@ use nom::{
     IResult,
 };
 
-use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 use std::io::{Cursor, Read};
 
@@ -206,13 +205,15 @@ fn parse_mindroid(line: &str) -> IResult<&str, Record> {
     Ok(mindroid)
 }
 
+// ------ SYSTEM LOG (logcat -v threadtime -v printable -v uid -d *:v) ------
 pub fn bugreport_section(line: &str) -> IResult<&str, (String, String)> {
-    let (line, logtag) = take_until("(")(line)?;
-    let (line, _) = char('(')(line)?;
-    let (line, msg) = take_until(")")(line)?;
-    let (line, _) = char(')')(line)?;
+    let (line, _) = tag("------ ")(line)?;
+    let (line, name) = take_until(" (")(line)?;
+    let (line, _) = tag(" (")(line)?;
+    let (line, command) = take_until(") ------")(line)?;
+    let (line, _) = tag(") ------")(line)?;
 
-    Ok((line, (logtag.to_string(), msg.to_string())))
+    Ok((line, (name.trim().to_string(), command.to_string())))
 }
 
 pub fn property(line: &str) -> IResult<&str, (String, String)> {
@@ -248,40 +249,12 @@ pub struct CsvParser;
 
 impl FormatParser for CsvParser {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
-        // Shadow struct with non separate tag
-        #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-        pub struct CsvRecord {
-            timestamp: Option<Timestamp>,
-            message: String,
-            level: Level,
-            tag: String,
-            process: String,
-            thread: String,
-            raw: String,
-        }
         let reader = Cursor::new(line).chain(Cursor::new([b'\n']));
         let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(reader);
         if let Some(result) = rdr.deserialize::<CsvRecord>().next() {
-            let record = result.map_err(|e| ParserError(format!("{e}")))?;
-            let CsvRecord {
-                timestamp,
-                message,
-                level,
-                tag,
-                process,
-                thread,
-                raw,
-            } = record;
-            let record = Record {
-                timestamp,
-                message,
-                level,
-                tags: tag.split(',').map(|s| s.trim().to_owned()).collect(),
-                process,
-                thread,
-                raw,
-            };
-            Ok(record)
+            result
+                .map(Record::from)
+                .map_err(|e| ParserError(format!("{e}")))
         } else {
             Err(ParserError("Failed to parse csv".to_string()))
         }
@@ -296,7 +269,59 @@ impl FormatParser for JsonParser {
     }
 }
 
-// [seconds][pid][tid][tags] LEVEL: message
+/// Returns true if `line` starts with a fuchsia timestamp like `[01086.023158]`.
+/// Lines from `ffx log` without this prefix are continuations of the previous record.
+pub fn is_fuchsia_record(line: &str) -> bool {
+    line.strip_prefix('[')
+        .and_then(|l| l.split_once(']'))
+        .map(|(ts, _)| !ts.is_empty() && ts.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .unwrap_or(false)
+}
+
+fn fuchsia_level(level: &str) -> Level {
+    match level {
+        "TRACE" => Level::Trace,
+        "VERBOSE" => Level::Verbose,
+        "DEBUG" => Level::Debug,
+        "INFO" => Level::Info,
+        "WARN" | "WARNING" => Level::Warn,
+        "ERROR" => Level::Error,
+        "FATAL" => Level::Fatal,
+        _ => Level::None,
+    }
+}
+
+/// Strip a `[file.cc(123)] ` or `[file.rs:123] ` prefix from a message and
+/// return the location as `file:line`
+fn fuchsia_location(message: &str) -> (Option<String>, &str) {
+    fn location(line: &str) -> IResult<&str, String> {
+        let (line, _) = char('[')(line)?;
+        let (line, file) = take_till1(|c| c == '(' || c == ':' || c == ']' || c == ' ')(line)?;
+        let (line, number) = alt((
+            |l| {
+                let (l, _) = char('(')(l)?;
+                let (l, n) = digit1(l)?;
+                let (l, _) = char(')')(l)?;
+                Ok((l, n))
+            },
+            |l| {
+                let (l, _) = char(':')(l)?;
+                digit1(l)
+            },
+        ))(line)?;
+        let (line, _) = char(']')(line)?;
+        let (line, _) = space0(line)?;
+        Ok((line, format!("{file}:{number}")))
+    }
+
+    match location(message) {
+        Ok((message, location)) => (Some(location), message),
+        Err(_) => (None, message),
+    }
+}
+
+// [seconds][moniker][tags] LEVEL: message
+// [seconds][pid][tid][moniker][tags] LEVEL: message
 // [01086.023158][boot-drivers:dev][driver,platform_bus] INFO: [platform-bus.cc(292)] Boot Item ZBI_TYPE_SERIAL_NUMBER not found
 fn parse_fuchsia(line: &str) -> IResult<&str, Record> {
     // Timestamp
@@ -305,53 +330,51 @@ fn parse_fuchsia(line: &str) -> IResult<&str, Record> {
     let (rest, _) = char(']')(rest)?;
     let timestamp = timestamp.parse::<f64>().map(Timestamp::from_secs).ok();
 
-    // Process
-    let (rest, _) = char('[')(rest)?;
-    let (rest, process) = take_until1("]")(rest)?;
-    let process = process.to_string();
-    let (rest, _) = char(']')(rest)?;
-
-    // // Tid
-    // let (rest, thread) = if rest.starts_with('[') {
-    //     let (rest, _) = char('[')(rest)?;
-    //     let (rest, thread) = take_until1("]")(rest)?;
-    //     let (rest, _) = char(']')(rest)?;
-    //     (rest, thread.to_string())
-    // } else {
-    //     (rest, String::new())
-    // };
-
-    // Tags
-    let (rest, tags) = if rest.starts_with('[') {
-        let (rest, _) = char('[')(rest)?;
-        let (rest, tags) = take_until1("]")(rest)?;
-        let (rest, _) = char(']')(rest)?;
-        let mut tags = tags
-            .split(',')
-            .map(|s| s.trim().to_owned())
-            .collect::<Vec<_>>();
-        tags.sort();
-        (rest, tags)
+    // Up to four bracketed fields: optional pid and tid, the moniker and optional tags
+    let (rest, mut fields) = many1(|l| {
+        let (l, _) = char('[')(l)?;
+        let (l, field) = take_until1("]")(l)?;
+        let (l, _) = char(']')(l)?;
+        Ok((l, field))
+    })(rest)?;
+
+    let is_id = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    // The moniker identifies the process. Keep the tid as thread.
+    let tid = if fields.len() >= 3 && is_id(fields[0]) && is_id(fields[1]) {
+        fields.drain(..2).nth(1)
     } else {
-        (rest, vec![])
+        None
     };
 
+    if fields.len() > 2 {
+        return Err(nom::Err::Error(Error::new(
+            rest,
+            nom::error::ErrorKind::Count,
+        )));
+    }
+
+    let process = fields[0].to_string();
+    let mut tags = fields
+        .get(1)
+        .map(|tags| {
+            tags.split(',')
+                .map(|s| s.trim().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    tags.sort();
+
+    let thread = tid.map(ToOwned::to_owned).unwrap_or_default();
+
     let (rest, _) = char(' ')(rest)?;
 
     // Level
-    let (rest, level) = take_until1(":")(rest)?;
+    let (rest, level) = take_till1(|c: char| c == ':' || c.is_whitespace())(rest)?;
     let (rest, _) = char(':')(rest)?;
-    let level = match level {
-        "TRACE" => Level::Trace,
-        "DEBUG" => Level::Debug,
-        "INFO" => Level::Info,
-        "WARN" => Level::Warn,
-        "ERROR" => Level::Error,
-        "FATAL" => Level::Fatal,
-        _ => unreachable!("unimplemented level: {}", level),
-    };
+    let level = fuchsia_level(level);
 
     let (_, message) = nom::combinator::rest(rest)?;
+    let (location, message) = fuchsia_location(message.trim());
 
     let record = Record {
         timestamp,
@@ -359,6 +382,8 @@ fn parse_fuchsia(line: &str) -> IResult<&str, Record> {
         level,
         tags,
         process,
+        thread,
+        location,
         ..Default::default()
     };
 
@@ -392,22 +417,52 @@ impl Default for Parser {
 
 impl Parser {
     pub fn parse(&mut self, raw: String) -> Record {
+        // Seems that we cannot parse this record
+        // Treat the raw input as message
+        self.try_parse(raw).unwrap_or_else(|raw| Record {
+            message: raw.clone(),
+            raw,
+            ..Default::default()
+        })
+    }
+
+    /// Parse raw and hand it back if no parser matches
+    pub fn try_parse(&mut self, raw: String) -> Result<Record, String> {
         for (index, parser) in self.0.iter().map(Box::as_ref).enumerate() {
             if let Ok(mut record) = parser.try_parse_str(raw.as_str()) {
                 if index > 0 {
                     self.0.swap(index, index - 1);
                 }
                 record.raw = raw;
-                return record;
+                return Ok(record);
             }
         }
+        Err(raw)
+    }
+}
 
-        // Seems that we cannot parse this record
-        // Treat the raw input as message
-        Record {
-            message: raw.clone(),
-            raw,
-            ..Default::default()
+/// Parse `line` in `format` without probing other formats. Records parsed
+/// from csv or json keep their raw field.
+pub fn parse_format(format: &Format, line: &str) -> Result<Record, ParserError> {
+    match format {
+        Format::Csv => CsvParser.try_parse_str(line),
+        Format::Json => JsonParser.try_parse_str(line),
+        Format::Raw => {
+            let parsers: [&dyn FormatParser; 3] = [&DefaultParser, &MindroidParser, &FuchsiaParser];
+            let mut error = None;
+            for parser in parsers.iter() {
+                match parser.try_parse_str(line) {
+                    Ok(mut record) => {
+                        record.raw = line.to_owned();
+                        return Ok(record);
+                    }
+                    Err(e) => error = Some(e),
+                }
+            }
+            Err(error.expect("No parser"))
+        }
+        Format::Html | Format::Human | Format::Sqlite => {
+            Err(ParserError(format!("Cannot parse {format}")))
         }
     }
 }
@@ -590,6 +645,64 @@ fn test_parse_csv() {
     );
 }
 
+#[test]
+fn test_parse_csv_output() {
+    let record = Record {
+        message: "make: *** [all] Error 2".into(),
+        tags: vec!["make".into()],
+        raw: "make: *** [all] Error 2".into(),
+        output: Some(crate::record::Output::Stderr),
+        ..Default::default()
+    };
+    let csv = crate::record::Format::Csv.fmt_record(&record).unwrap();
+    let r = CsvParser.try_parse_str(&csv).unwrap();
+    assert_eq!(r.output, Some(crate::record::Output::Stderr));
+    assert_eq!(r.message, record.message);
+}
+
+#[test]
+fn test_parse_csv_tags() {
+    for tags in [
+        vec![],
+        vec!["a".to_owned()],
+        vec!["a".to_owned(), "b".to_owned()],
+    ] {
+        let record = Record {
+            tags,
+            level: Level::Warn,
+            raw: "foo, bar".into(),
+            ..Default::default()
+        };
+        let csv = Format::Csv.fmt_record(&record).unwrap();
+        assert_eq!(parse_format(&Format::Csv, &csv).unwrap(), record);
+    }
+}
+
+#[test]
+fn test_parse_csv_elapsed() {
+    let record = Record {
+        raw: "a".into(),
+        ..Default::default()
+    };
+    let flagged = Record {
+        elapsed: Some(1.5),
+        gap: true,
+        ..record.clone()
+    };
+    let plain = Format::Csv.fmt_record(&record).unwrap();
+    let csv = Format::Csv.fmt_record(&flagged).unwrap();
+    // Records without elapsed time keep the columns
+    assert_eq!(plain.matches(',').count(), csv.matches(',').count());
+    assert_eq!(parse_format(&Format::Csv, &plain).unwrap(), record);
+    assert_eq!(parse_format(&Format::Csv, &csv).unwrap(), flagged);
+    // Captures without the columns
+    let old = plain.rsplitn(3, ',').last().unwrap();
+    assert_eq!(parse_format(&Format::Csv, old).unwrap(), record);
+
+    let json = Format::Json.fmt_record(&flagged).unwrap();
+    assert!(json.ends_with(r#""elapsed":1.5,"gap":true}"#));
+}
+
 #[test]
 fn parse_property() {
     let t = "[ro.build.tags]: [release-keys]";
@@ -599,6 +712,38 @@ fn parse_property() {
     );
 }
 
+#[test]
+fn parse_bugreport_section() {
+    let t = "------ SYSTEM LOG (logcat -v threadtime -v printable -d *:v) ------";
+    assert_eq!(
+        bugreport_section(t).unwrap().1,
+        (
+            "SYSTEM LOG".to_owned(),
+            "logcat -v threadtime -v printable -d *:v".to_owned()
+        )
+    );
+    assert!(bugreport_section("------ 0.012s was the duration of 'SYSTEM LOG' ------").is_err());
+    assert_eq!(
+        bugreport_section("------ CPU INFO (top -b -n 1 -H -s 6 -o pid,tid,user) ------")
+            .unwrap()
+            .1,
+        (
+            "CPU INFO".to_owned(),
+            "top -b -n 1 -H -s 6 -o pid,tid,user".to_owned()
+        )
+    );
+    // Commands that contain parentheses
+    assert_eq!(
+        bugreport_section("------ DUMPSYS (/system/bin/dumpsys -t 10 (critical)) ------")
+            .unwrap()
+            .1
+             .1,
+        "/system/bin/dumpsys -t 10 (critical)"
+    );
+    assert!(bugreport_section("07-01 14:13:14.446   225   295 I Tag: (foo) bar").is_err());
+    assert!(bugreport_section("------ SYSTEM LOG ------").is_err());
+}
+
 #[test]
 fn test_parse_section() {
     let mut p = Parser::default();
@@ -624,10 +769,8 @@ fn test_parse_fuchsia() {
     assert_eq!(r.level, Level::Info);
     assert_eq!(r.process, "boot-drivers:dev");
     assert_eq!(r.tags, vec!("driver", "platform_bus"));
-    assert_eq!(
-        r.message,
-        "[platform-bus.cc(292)] Boot Item ZBI_TYPE_SERIAL_NUMBER not found"
-    );
+    assert_eq!(r.location, Some("platform-bus.cc:292".to_owned()));
+    assert_eq!(r.message, "Boot Item ZBI_TYPE_SERIAL_NUMBER not found");
 
     let r = p
         .try_parse_str("[01086.023158][klog] INFO: [foo] blah")
@@ -635,6 +778,7 @@ fn test_parse_fuchsia() {
     assert_eq!(r.level, Level::Info);
     assert_eq!(r.process, "klog");
     assert!(r.tags.is_empty());
+    assert_eq!(r.location, None);
     assert_eq!(r.message, "[foo] blah");
 
     let r = p
@@ -648,3 +792,50 @@ fn test_parse_fuchsia() {
         "ignoring Reply to Information-Request: missing Server Id option"
     );
 }
+
+#[test]
+fn test_parse_fuchsia_pid_tid() {
+    let p = FuchsiaParser {};
+    let r = p
+        .try_parse_str(
+            "[00012.345678][1234][5678][netstack][dhcp,ipv4] DEBUG: [src/lib.rs:42] lease acquired",
+        )
+        .unwrap();
+    assert_eq!(r.level, Level::Debug);
+    assert_eq!(r.process, "netstack");
+    assert_eq!(r.thread, "5678");
+    assert_eq!(r.tags, vec!("dhcp", "ipv4"));
+    assert_eq!(r.location, Some("src/lib.rs:42".to_owned()));
+    assert_eq!(r.message, "lease acquired");
+
+    let r = p
+        .try_parse_str("[00012.345678][1234][5678][netstack] INFO: up")
+        .unwrap();
+    assert_eq!(r.process, "netstack");
+    assert_eq!(r.thread, "5678");
+    assert!(r.tags.is_empty());
+}
+
+#[test]
+fn test_parse_fuchsia_unknown_level() {
+    let p = FuchsiaParser {};
+    let r = p
+        .try_parse_str("[01086.023158][klog] SEVERITY_42: something odd")
+        .unwrap();
+    assert_eq!(r.level, Level::None);
+    assert_eq!(r.message, "something odd");
+}
+
+#[test]
+fn test_parse_fuchsia_multiline() {
+    let p = FuchsiaParser {};
+    let r = p
+        .try_parse_str("[01086.023158][klog] ERROR: panic:\n  frame 0\n  frame 1")
+        .unwrap();
+    assert_eq!(r.level, Level::Error);
+    assert_eq!(r.message, "panic:\n  frame 0\n  frame 1");
+
+    assert!(is_fuchsia_record("[01086.023158][klog] ERROR: panic:"));
+    assert!(!is_fuchsia_record("  frame 0"));
+    assert!(!is_fuchsia_record("[foo] bar"));
+}
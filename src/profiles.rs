@@ -18,17 +18,29 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::utils;
-use clap::{value_t, ArgMatches};
+use crate::{cli, highlight::Rule, trigger::Trigger, utils};
+use clap::{crate_name, value_t, AppSettings, ArgMatches, ErrorKind};
 use failure::{format_err, Error};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap, convert::Into, env::var, fs::File, io::Read, ops::AddAssign,
-    path::PathBuf,
+    collections::HashMap,
+    convert::Into,
+    env::{self, var},
+    ffi::OsString,
+    fmt::Display,
+    fs::{self, File},
+    io::Read,
+    mem,
+    ops::AddAssign,
+    path::{Path, PathBuf},
 };
 use toml::from_str;
 
 const DEFAULT_PROFILE_NAME: &str = "default";
+/// Name of project profile files
+const PROJECT_FILE: &str = ".rogcat.toml";
+const RECURSION_LIMIT: usize = 100;
 
 /// Profile definition with filters and misc
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -37,81 +49,739 @@ pub struct Profile {
     pub extends: Vec<String>,
     pub filter: Vec<String>,
     pub filter_case_insensitive: Vec<String>,
+    /// Highlight rules e.g `FATAL => bold red`
     pub highlight: Vec<String>,
     pub message: Vec<String>,
     pub message_case_insensitive: Vec<String>,
     pub tag: Vec<String>,
     pub tag_case_insensitive: Vec<String>,
+    /// Trigger rules e.g `-m ANR => bugreport`
+    pub trigger: Vec<String>,
+    /// Additional environment of COMMAND
+    pub env: HashMap<String, String>,
+    /// Working directory of COMMAND
+    pub cwd: Option<PathBuf>,
+    /// Lists that replace the lists of extended profiles instead of merging
+    pub replace: Vec<String>,
+    /// Command line options
+    pub options: Options,
 }
 
-/// Create a new Profiles instance from a give configuration file
-/// and default if file is not present or readable
+/// Command line options that can be set in a profile. Options passed on the
+/// command line take precedence.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Options {
+    pub command: Option<Vec<String>>,
+    pub input: Option<Vec<String>>,
+    pub output: Option<String>,
+    pub replay: Option<bool>,
+    pub restart: Option<bool>,
+    pub exit_code: Option<bool>,
+    pub buffer: Option<Vec<String>>,
+    pub serial: Option<String>,
+    pub last: Option<bool>,
+    pub dump: Option<bool>,
+    pub head: Option<usize>,
+    pub tail: Option<usize>,
+    pub format: Option<String>,
+    pub color: Option<String>,
+    pub no_dimm: Option<bool>,
+    pub bright_colors: Option<bool>,
+    pub theme: Option<String>,
+    pub hide_timestamp: Option<bool>,
+    pub show_date: Option<bool>,
+    pub wrap: Option<String>,
+    pub compact: Option<bool>,
+    pub pager: Option<bool>,
+    pub no_pager: Option<bool>,
+    pub time: Option<String>,
+    pub time_marker: Option<String>,
+    pub time_gap: Option<String>,
+    pub fts: Option<String>,
+    #[serde(rename = "where")]
+    pub condition: Option<String>,
+    pub replay_speed: Option<String>,
+    pub replay_max_gap: Option<String>,
+    pub replay_start: Option<String>,
+    pub overwrite: Option<bool>,
+    pub records_per_file: Option<String>,
+    pub filename_format: Option<String>,
+    pub crash_reports: Option<String>,
+    pub level: Option<String>,
+    pub message_only: Option<bool>,
+    pub package: Option<Vec<String>>,
+    pub ffx: Option<bool>,
+    pub since: Option<String>,
+    pub remote_filter: Option<String>,
+    pub shell: Option<bool>,
+}
+
+impl Options {
+    /// Command line arguments of the options as pairs of argument name and
+    /// tokens. Arguments that are required by others come first.
+    fn args(&self) -> Vec<(&'static str, Vec<String>)> {
+        fn value<T: Display>(long: &str, value: &Option<T>) -> Vec<String> {
+            value.iter().map(|v| format!("--{long}={v}")).collect()
+        }
+        fn values(long: &str, values: &Option<Vec<String>>) -> Vec<String> {
+            values
+                .iter()
+                .flatten()
+                .map(|v| format!("--{long}={v}"))
+                .collect()
+        }
+        fn flag(long: &str, value: Option<bool>) -> Vec<String> {
+            if value == Some(true) {
+                vec![format!("--{long}")]
+            } else {
+                Vec::new()
+            }
+        }
+
+        vec![
+            // Multiple values after -- are taken as subcommand. Pass a single
+            // COMMAND that is split like a shell would do.
+            (
+                "COMMAND",
+                self.command
+                    .iter()
+                    .map(|c| match c.as_slice() {
+                        [c] => c.clone(),
+                        c => shell_words::join(c),
+                    })
+                    .collect(),
+            ),
+            ("input", values("input", &self.input)),
+            ("output", value("output", &self.output)),
+            ("replay", flag("replay", self.replay)),
+            ("restart", flag("restart", self.restart)),
+            ("exit-code", flag("exit-code", self.exit_code)),
+            ("buffer", values("buffer", &self.buffer)),
+            ("dev", value("serial", &self.serial)),
+            ("last", flag("last", self.last)),
+            ("dump", flag("dump", self.dump)),
+            ("head", value("head", &self.head)),
+            ("tail", value("tail", &self.tail)),
+            ("format", value("format", &self.format)),
+            ("color", value("color", &self.color)),
+            ("no-dimm", flag("no-dimm", self.no_dimm)),
+            ("bright_colors", flag("bright-colors", self.bright_colors)),
+            ("theme", value("theme", &self.theme)),
+            (
+                "hide-timestamp",
+                flag("hide-timestamp", self.hide_timestamp),
+            ),
+            ("show-date", flag("show-date", self.show_date)),
+            ("wrap", value("wrap", &self.wrap)),
+            ("compact", flag("compact", self.compact)),
+            ("pager", flag("pager", self.pager)),
+            ("no-pager", flag("no-pager", self.no_pager)),
+            ("time", value("time", &self.time)),
+            ("time-marker", value("time-marker", &self.time_marker)),
+            ("time-gap", value("time-gap", &self.time_gap)),
+            ("fts", value("fts", &self.fts)),
+            ("where", value("where", &self.condition)),
+            ("replay-speed", value("replay-speed", &self.replay_speed)),
+            (
+                "replay-max-gap",
+                value("replay-max-gap", &self.replay_max_gap),
+            ),
+            ("replay-start", value("replay-start", &self.replay_start)),
+            ("overwrite", flag("overwrite", self.overwrite)),
+            (
+                "records-per-file",
+                value("records-per-file", &self.records_per_file),
+            ),
+            (
+                "filename-format",
+                value("filename-format", &self.filename_format),
+            ),
+            ("crash-reports", value("crash-reports", &self.crash_reports)),
+            ("level", value("level", &self.level)),
+            ("message_only", flag("message-only", self.message_only)),
+            ("package", values("package", &self.package)),
+            ("fuchsia", flag("ffx", self.ffx)),
+            ("since", value("since", &self.since)),
+            ("remote-filter", value("remote-filter", &self.remote_filter)),
+            ("shell", flag("shell", self.shell)),
+        ]
+        .into_iter()
+        .filter(|(_, tokens)| !tokens.is_empty())
+        .collect()
+    }
+}
+
+impl AddAssign for Options {
+    /// Options of `self` take precedence. Lists are merged.
+    fn add_assign(&mut self, other: Options) {
+        macro_rules! scalar {
+            ($($x:ident),*) => {
+                $(if self.$x.is_none() {
+                    self.$x = other.$x;
+                })*
+            };
+        }
+        macro_rules! list {
+            ($($x:ident),*) => {
+                $(match (&mut self.$x, other.$x) {
+                    (Some(x), Some(y)) => {
+                        let y = y.into_iter().filter(|v| !x.contains(v)).collect::<Vec<_>>();
+                        x.extend(y);
+                    }
+                    (x @ None, y) => *x = y,
+                    _ => (),
+                })*
+            };
+        }
+
+        list!(input, buffer, package);
+        scalar!(
+            command,
+            output,
+            replay,
+            restart,
+            exit_code,
+            serial,
+            last,
+            dump,
+            head,
+            tail,
+            format,
+            color,
+            no_dimm,
+            bright_colors,
+            theme,
+            hide_timestamp,
+            show_date,
+            wrap,
+            compact,
+            pager,
+            no_pager,
+            time,
+            time_marker,
+            time_gap,
+            fts,
+            condition,
+            replay_speed,
+            replay_max_gap,
+            replay_start,
+            overwrite,
+            records_per_file,
+            filename_format,
+            crash_reports,
+            level,
+            message_only,
+            ffx,
+            since,
+            remote_filter,
+            shell
+        );
+    }
+}
+
+/// Create a new Profiles instance from the profile files and default if no
+/// file is present. Multiple selected profiles are merged and the later ones
+/// take precedence.
 pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
-    let file = file(Some(args))?;
-    if !file.exists() {
-        Ok(Profile::default())
-    } else {
-        let mut config = String::new();
-        File::open(file.clone())
-            .map_err(|e| format_err!("Failed to open {}: {}", file.display(), e))?
-            .read_to_string(&mut config)?;
-
-        let mut config_file: ConfigurationFile = from_str(&config)
-            .map_err(|e| format_err!("Failed to parse {}: {}", file.display(), e))?;
-
-        let profiles: HashMap<String, Profile> = config_file
-            .profile
-            .drain()
-            .map(|(k, v)| (k, v.into()))
-            .collect();
-
-        let mut profile = Profile::default();
-        if let Some(n) = args.value_of("profile") {
-            profile = profiles
-                .get(n)
-                .ok_or_else(|| format_err!("Unknown profile {}", n))?
-                .clone();
-            expand(n, &mut profile, &profiles)?;
-        } else if let Some(default_profile) = profiles.get(DEFAULT_PROFILE_NAME) {
-            profile = default_profile.clone();
-            expand(DEFAULT_PROFILE_NAME, &mut profile, &profiles)?;
-        }
-
-        Ok(profile)
-    }
-}
-
-/// Expand a profile with file content
-fn expand(n: &str, p: &mut Profile, a: &HashMap<String, Profile>) -> Result<(), Error> {
-    let mut recursion_limit = 100;
-    while !p.extends.is_empty() {
-        let extends = p.extends.clone();
-        p.extends.clear();
-        for e in &extends {
-            let f = a
-                .get(e)
-                .ok_or_else(|| format_err!("Unknown extend profile name {} used in {}", e, n))?;
-            *p += f.clone();
-        }
-
-        recursion_limit -= 1;
-        if recursion_limit == 0 {
+    let profiles = merge(&layers(Some(args))?);
+    let names = match args.values_of("profile") {
+        Some(names) => names.collect(),
+        None if profiles.contains_key(DEFAULT_PROFILE_NAME) => vec![DEFAULT_PROFILE_NAME],
+        None => Vec::new(),
+    };
+
+    let mut profile = Profile::default();
+    for n in names {
+        if !profiles.contains_key(n) {
+            return Err(format_err!("Unknown profile {}", n));
+        }
+        let mut p = expand(n, &profiles, 0)?;
+        p += profile;
+        profile = p;
+    }
+    Ok(profile)
+}
+
+/// Profile file and its content
+struct Layer {
+    path: PathBuf,
+    content: String,
+    config: ConfigurationFile,
+}
+
+/// Read the profile files and the files they include from the lowest to the
+/// highest precedence. Each file is read once.
+fn layers(args: Option<&ArgMatches>) -> Result<Vec<Layer>, Error> {
+    let mut layers = Vec::new();
+    let mut visited = Vec::new();
+    for file in files(args)? {
+        load(&file, &mut layers, &mut visited)?;
+    }
+    Ok(layers)
+}
+
+/// Profile files and the files they include. The user's profile file is
+/// part of it even if it does not exist yet.
+pub fn paths(args: &ArgMatches) -> Vec<PathBuf> {
+    let mut paths = match layers(Some(args)) {
+        Ok(layers) => layers.into_iter().map(|l| l.path).collect(),
+        Err(_) => files(Some(args)).unwrap_or_default(),
+    };
+    if !args.is_present("profiles-path") {
+        if let Ok(user) = file() {
+            if !paths.contains(&user) {
+                paths.push(user);
+            }
+        }
+    }
+    paths
+}
+
+/// Read `path` after the files it includes. Includes are relative to `path`.
+fn load(path: &Path, layers: &mut Vec<Layer>, visited: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| format_err!("Failed to open {}: {}", path.display(), e))?;
+    if visited.contains(&canonical) {
+        return Ok(());
+    }
+    visited.push(canonical);
+
+    let (content, config) = read(path)?;
+    for include in &config.include {
+        let include = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+        load(&include, layers, visited)
+            .map_err(|e| format_err!("{} (included from {})", e, path.display()))?;
+    }
+    layers.push(Layer {
+        path: path.to_owned(),
+        content,
+        config,
+    });
+    Ok(())
+}
+
+/// Read and parse the configuration file at `path`
+fn read(path: &Path) -> Result<(String, ConfigurationFile), Error> {
+    let mut content = String::new();
+    File::open(path)
+        .map_err(|e| format_err!("Failed to open {}: {}", path.display(), e))?
+        .read_to_string(&mut content)?;
+    let config =
+        from_str(&content).map_err(|e| format_err!("Failed to parse {}: {}", path.display(), e))?;
+    Ok((content, config))
+}
+
+/// Profiles of all layers by name. Profiles of the same name are merged like
+/// extends: a higher layer wins and can replace the lists of lower layers.
+fn merge(layers: &[Layer]) -> HashMap<String, Profile> {
+    let mut profiles = HashMap::<String, Profile>::new();
+    for layer in layers {
+        for (name, profile) in &layer.config.profile {
+            let mut profile = Profile::from(profile.clone());
+            if let Some(mut lower) = profiles.remove(name) {
+                for list in &profile.replace {
+                    lower.clear(list).ok();
+                }
+                profile += lower;
+            }
+            profiles.insert(name.clone(), profile);
+        }
+    }
+    profiles
+}
+
+/// Resolve the extends of profile `n`. Lists of the extended profiles are
+/// merged unless the profile replaces them. Scalars of the profile win.
+fn expand(n: &str, profiles: &HashMap<String, Profile>, depth: usize) -> Result<Profile, Error> {
+    if depth == RECURSION_LIMIT {
+        return Err(format_err!(
+            "Reached recursion limit while resolving profile {} extends",
+            n
+        ));
+    }
+    let mut profile = profiles
+        .get(n)
+        .ok_or_else(|| format_err!("Unknown profile {}", n))?
+        .clone();
+    for e in mem::take(&mut profile.extends) {
+        if !profiles.contains_key(&e) {
             return Err(format_err!(
-                "Reached recursion limit while resolving profile {} extends",
+                "Unknown extend profile name {} used in {}",
+                e,
                 n
             ));
         }
+        let mut extended = expand(&e, profiles, depth + 1)?;
+        for list in &profile.replace {
+            extended
+                .clear(list)
+                .map_err(|e| format_err!("Invalid replace in profile {}: {}", n, e))?;
+        }
+        profile += extended;
+    }
+    Ok(profile)
+}
+
+/// Add the options of `profile` to the command line. Options passed on the
+/// command line win. Options of the profile that conflict with or require
+/// options of the command line are skipped.
+pub fn apply(args: ArgMatches<'static>, profile: &Profile) -> Result<ArgMatches<'static>, Error> {
+    apply_to(env::args_os().collect(), &[], args, profile)
+}
+
+/// Add the options of `profile` to the arguments of the subcommand `path`
+/// e.g `["bugreport", "inspect"]`. Options the subcommand does not know are
+/// skipped.
+pub fn apply_subcommand(
+    path: &[&str],
+    args: &ArgMatches<'static>,
+    profile: &Profile,
+) -> Result<ArgMatches<'static>, Error> {
+    apply_to(env::args_os().collect(), path, args.clone(), profile)
+}
+
+fn apply_to(
+    argv: Vec<OsString>,
+    path: &[&str],
+    args: ArgMatches<'static>,
+    profile: &Profile,
+) -> Result<ArgMatches<'static>, Error> {
+    let candidates = profile
+        .options
+        .args()
+        .into_iter()
+        .filter(|(name, _)| args.occurrences_of(name) == 0)
+        .filter(|(name, _)| path.is_empty() || *name != "COMMAND")
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(args);
+    }
+
+    let invalid = |e: clap::Error| {
+        let message = e.message.lines().next().unwrap_or_default();
+        format_err!("Invalid profile: {}", message.trim_start_matches("error: "))
+    };
+    // Options of the profile go right after the binary or subcommand names
+    // and its COMMAND after the command line
+    let split = path.iter().fold(1, |i, name| {
+        argv.iter()
+            .skip(i)
+            .position(|a| a == name)
+            .map(|p| i + p + 1)
+            .unwrap_or(i)
+    });
+    let parse = |options: &[String], command: &[String], with_args: bool| {
+        let mut tokens = argv.iter().take(split).cloned().collect::<Vec<_>>();
+        tokens.extend(options.iter().map(OsString::from));
+        if with_args {
+            tokens.extend(argv.iter().skip(split).cloned());
+        }
+        if !command.is_empty() {
+            tokens.push("--".into());
+            tokens.extend(command.iter().map(OsString::from));
+        }
+        cli::cli()
+            .setting(AppSettings::ColorNever)
+            .get_matches_from_safe(tokens)
+            .map(|matches| {
+                path.iter().fold(matches, |m, name| {
+                    m.subcommand_matches(name)
+                        .cloned()
+                        .expect("Missing subcommand")
+                })
+            })
+    };
+
+    let mut options = Vec::new();
+    let mut command = Vec::new();
+    for (name, tokens) in candidates {
+        let (o, c) = if name == "COMMAND" {
+            (options.clone(), tokens)
+        } else {
+            ([options.as_slice(), &tokens].concat(), command.clone())
+        };
+        match parse(&o, &c, true) {
+            Ok(_) => {
+                options = o;
+                command = c;
+            }
+            Err(ref e) if e.kind == ErrorKind::UnknownArgument && !path.is_empty() => (),
+            Err(e)
+                if e.kind == ErrorKind::ArgumentConflict
+                    || e.kind == ErrorKind::MissingRequiredArgument =>
+            {
+                if let Err(e) = parse(&o, &c, false) {
+                    if e.kind == ErrorKind::ArgumentConflict {
+                        return Err(invalid(e));
+                    }
+                }
+            }
+            Err(e) => return Err(invalid(e)),
+        }
+    }
+
+    parse(&options, &command, true).map_err(invalid)
+}
+
+/// Print the names and comments of the profiles
+pub fn list(args: &ArgMatches) -> Result<(), Error> {
+    let mut profiles = merge(&layers(Some(args))?).into_iter().collect::<Vec<_>>();
+    profiles.sort_by(|a, b| a.0.cmp(&b.0));
+    let width = profiles.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    for (name, profile) in profiles {
+        let comment = profile.comment.unwrap_or_default();
+        println!("{}", format!("{name:width$}  {comment}").trim_end());
     }
     Ok(())
 }
 
-/// Return path to profile file by checking cli argument, env and default to configdir
-fn file(args: Option<&ArgMatches>) -> Result<PathBuf, Error> {
+/// Print profile `name` with its extends resolved
+pub fn show(args: &ArgMatches, name: &str) -> Result<(), Error> {
+    let profiles = merge(&layers(Some(args))?);
+    let profile = expand(name, &profiles, 0)?;
+    print!("{}", to_string(name, profile)?);
+    Ok(())
+}
+
+/// Format `profile` as section of a configuration file
+fn to_string(name: &str, profile: Profile) -> Result<String, Error> {
+    let mut config = ConfigurationFile::default();
+    config.profile.insert(name.to_owned(), profile.into());
+    // Values must be written before tables
+    toml::to_string(&toml::Value::try_from(config)?)
+        .map_err(|e| format_err!("Failed to format profile {}: {}", name, e))
+}
+
+/// Check the regular expressions, triggers, replace lists and options of
+/// `profile`. Returns the field and description of each problem.
+fn check(profile: &Profile) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    let lists = [
+        ("filter", &profile.filter),
+        ("filter_case_insensitive", &profile.filter_case_insensitive),
+        ("message", &profile.message),
+        (
+            "message_case_insensitive",
+            &profile.message_case_insensitive,
+        ),
+        ("tag", &profile.tag),
+        ("tag_case_insensitive", &profile.tag_case_insensitive),
+    ];
+    for (field, patterns) in lists.iter() {
+        for pattern in patterns.iter() {
+            if let Err(e) = Regex::new(pattern.strip_prefix('!').unwrap_or(pattern)) {
+                let e = e.to_string();
+                let e = e.lines().last().unwrap_or_default();
+                problems.push((*field, format!("Invalid regex \"{pattern}\": {e}")));
+            }
+        }
+    }
+    for h in &profile.highlight {
+        if let Err(e) = Rule::parse(h) {
+            problems.push(("highlight", e.to_string()));
+        }
+    }
+    for t in &profile.trigger {
+        if let Err(e) = Trigger::parse(t) {
+            problems.push(("trigger", e.to_string()));
+        }
+    }
+    for list in &profile.replace {
+        if let Err(e) = Profile::default().clear(list) {
+            problems.push(("replace", format!("Invalid replace: {e}")));
+        }
+    }
+    let argv = vec![OsString::from(crate_name!())];
+    let args = cli::cli().get_matches_from(argv.clone());
+    if let Err(e) = apply_to(argv, &[], args, profile) {
+        problems.push(("", e.to_string()));
+    }
+    problems
+}
+
+/// True if the extends of `name` lead back to a profile on `stack`. The
+/// cycle is left on `stack`.
+fn cycle(name: &str, profiles: &HashMap<String, Profile>, stack: &mut Vec<String>) -> bool {
+    if stack.iter().any(|n| n == name) {
+        stack.push(name.to_owned());
+        return true;
+    }
+    stack.push(name.to_owned());
+    for e in profiles
+        .get(name)
+        .map(|p| p.extends.as_slice())
+        .unwrap_or_default()
+    {
+        if cycle(e, profiles, stack) {
+            return true;
+        }
+    }
+    stack.pop();
+    false
+}
+
+/// Line number of `field` in the section of profile `name` in `content`.
+/// Falls back to the line of the section header.
+fn line_of(content: &str, name: &str, field: &str) -> Option<usize> {
+    let is_header = |line: &str| {
+        line.starts_with('[')
+            && from_str::<ConfigurationFile>(line)
+                .map(|c| c.profile.contains_key(name))
+                .unwrap_or(false)
+    };
+    let lines = content.lines().map(str::trim).collect::<Vec<_>>();
+    let header = lines.iter().position(|l| is_header(l))?;
+    let field = lines
+        .iter()
+        .enumerate()
+        .skip(header + 1)
+        .take_while(|(_, l)| !l.starts_with('['))
+        .find(|(_, l)| {
+            !field.is_empty()
+                && l.strip_prefix(field)
+                    .map(|r| r.trim_start().starts_with('='))
+                    .unwrap_or(false)
+        })
+        .map(|(n, _)| n);
+    Some(field.unwrap_or(header) + 1)
+}
+
+/// Format a problem with `field` of profile `name` in `layer`
+fn problem(layer: &Layer, name: &str, field: &str, description: &str) -> String {
+    let location = match line_of(&layer.content, name, field) {
+        Some(line) => format!("{}:{}", layer.path.display(), line),
+        None => layer.path.display().to_string(),
+    };
+    format!("{location}: {name}: {description}")
+}
+
+/// Check all profiles of the profile files. Returns the problems as
+/// `file:line: profile: description`.
+pub fn validate(args: &ArgMatches) -> Result<Vec<String>, Error> {
+    let layers = layers(Some(args))?;
+    let mut problems = Vec::new();
+
+    for layer in &layers {
+        let mut unknown = layer.config.unknown.keys().collect::<Vec<_>>();
+        unknown.sort();
+        for field in unknown {
+            problems.push(format!("{}: Unknown field {}", layer.path.display(), field));
+        }
+
+        let mut names = layer.config.profile.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let profile = &layer.config.profile[name];
+            let mut unknown = profile.unknown.keys().collect::<Vec<_>>();
+            unknown.sort();
+            for field in unknown {
+                let description = format!("Unknown field {field}");
+                problems.push(problem(layer, name, field, &description));
+            }
+            for (field, description) in check(&profile.clone().into()) {
+                problems.push(problem(layer, name, field, &description));
+            }
+        }
+    }
+
+    // Extends are resolved across all layers
+    let profiles = merge(&layers);
+    let mut names = profiles.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let layer = layers
+            .iter()
+            .rev()
+            .find(|l| l.config.profile.contains_key(name))
+            .expect("Profile without file");
+        let profile = &profiles[name];
+        for e in profile
+            .extends
+            .iter()
+            .filter(|e| !profiles.contains_key(*e))
+        {
+            let description = format!("Unknown extend profile name {e}");
+            problems.push(problem(layer, name, "extends", &description));
+        }
+        let mut stack = Vec::new();
+        if cycle(name, &profiles, &mut stack) && stack.last() == Some(name) {
+            let description = format!("Cycle in extends {}", stack.join(" -> "));
+            problems.push(problem(layer, name, "extends", &description));
+        }
+    }
+    Ok(problems)
+}
+
+/// Save the filters of `args` as profile `name`. The profile is appended to
+/// the configuration file that is created if needed.
+pub fn save(args: &ArgMatches, name: &str, filters: &ArgMatches) -> Result<PathBuf, Error> {
+    let path = match args.value_of("profiles-path") {
+        Some(path) => PathBuf::from(path),
+        None => file()?,
+    };
+    let mut content = String::new();
+    if path.exists() {
+        let (c, config) = read(&path)?;
+        if config.profile.contains_key(name) {
+            return Err(format_err!("Profile {} exists in {}", name, path.display()));
+        }
+        content = c;
+    }
+
+    let values = |name: &str| {
+        filters
+            .values_of(name)
+            .map(|v| v.map(ToOwned::to_owned).collect())
+            .unwrap_or_default()
+    };
+    let profile = Profile {
+        comment: filters.value_of("comment").map(ToOwned::to_owned),
+        extends: values("extends"),
+        filter: values("filter"),
+        filter_case_insensitive: values("filter-case-insensitive"),
+        highlight: values("highlight"),
+        message: values("message"),
+        message_case_insensitive: values("message-case-insensitive"),
+        tag: values("tag"),
+        tag_case_insensitive: values("tag-case-insensitive"),
+        trigger: values("trigger"),
+        options: Options {
+            level: filters.value_of("level").map(ToOwned::to_owned),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    if let Some((_, e)) = check(&profile).into_iter().next() {
+        return Err(format_err!("Invalid profile {}: {}", name, e));
+    }
+
+    if !content.is_empty() {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push('\n');
+    }
+    content.push_str(&to_string(name, profile)?);
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .map_err(|e| format_err!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&path, content)
+        .map_err(|e| format_err!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Profile files from the lowest to the highest precedence: the nearest
+/// project file in the current directory or its parents and the user's file.
+/// A file passed with --profiles-path is used alone.
+fn files(args: Option<&ArgMatches>) -> Result<Vec<PathBuf>, Error> {
     if let Some(args) = args {
         if args.is_present("profiles-path") {
             let f = PathBuf::from(value_t!(args, "profiles-path", String)?);
             if f.exists() {
-                return Ok(f);
+                return Ok(vec![f]);
             } else {
                 return Err(format_err!(
                     "Cannot find {}. Use --profiles_path to specify the path manually!",
@@ -120,6 +790,18 @@ fn file(args: Option<&ArgMatches>) -> Result<PathBuf, Error> {
             }
         }
     }
+
+    let project = env::current_dir().ok().and_then(|dir| {
+        dir.ancestors()
+            .map(|d| d.join(PROJECT_FILE))
+            .find(|f| f.is_file())
+    });
+    let user = Some(file()?).filter(|f| f.exists());
+    Ok(project.into_iter().chain(user).collect())
+}
+
+/// Return path to the user's profile file by checking env and default to configdir
+fn file() -> Result<PathBuf, Error> {
     if let Ok(f) = var("ROGCAT_PROFILES").map(PathBuf::from) {
         if f.exists() {
             Ok(f)
@@ -137,7 +819,14 @@ fn file(args: Option<&ArgMatches>) -> Result<PathBuf, Error> {
 /// Configuration file
 #[derive(Debug, Default, Deserialize, Serialize)]
 struct ConfigurationFile {
+    /// Files with profiles that this file extends or overrides
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<PathBuf>,
+    #[serde(default)]
     profile: HashMap<String, ProfileFile>,
+    /// Fields that are not known to rogcat
+    #[serde(flatten, skip_serializing)]
+    unknown: HashMap<String, toml::Value>,
 }
 
 /// Struct with exact layout as used in config file
@@ -147,11 +836,44 @@ struct ProfileFile {
     extends: Option<Vec<String>>,
     filter: Option<Vec<String>>,
     filter_case_insensitive: Option<Vec<String>>,
-    highlight: Option<Vec<String>>,
+    highlight: Option<Vec<HighlightFile>>,
     message: Option<Vec<String>>,
     message_case_insensitive: Option<Vec<String>>,
     tag: Option<Vec<String>>,
     tag_case_insensitive: Option<Vec<String>>,
+    trigger: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    cwd: Option<PathBuf>,
+    replace: Option<Vec<String>>,
+    #[serde(flatten)]
+    options: Options,
+    /// Fields that are not known to rogcat
+    #[serde(flatten, skip_serializing)]
+    unknown: HashMap<String, toml::Value>,
+}
+
+/// Highlight rule as string `PATTERN => STYLE` or table with pattern and style
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum HighlightFile {
+    Rule(String),
+    Table {
+        pattern: String,
+        style: Option<String>,
+    },
+}
+
+impl From<HighlightFile> for String {
+    fn from(h: HighlightFile) -> String {
+        match h {
+            HighlightFile::Rule(rule) => rule,
+            HighlightFile::Table {
+                pattern,
+                style: Some(style),
+            } => format!("{pattern} => {style}"),
+            HighlightFile::Table { pattern, .. } => pattern,
+        }
+    }
 }
 
 impl From<ProfileFile> for Profile {
@@ -161,11 +883,77 @@ impl From<ProfileFile> for Profile {
             extends: f.extends.unwrap_or_default(),
             filter: f.filter.unwrap_or_default(),
             filter_case_insensitive: f.filter_case_insensitive.unwrap_or_default(),
-            highlight: f.highlight.unwrap_or_default(),
+            highlight: f
+                .highlight
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
             message: f.message.unwrap_or_default(),
             message_case_insensitive: f.message_case_insensitive.unwrap_or_default(),
             tag: f.tag.unwrap_or_default(),
             tag_case_insensitive: f.tag_case_insensitive.unwrap_or_default(),
+            trigger: f.trigger.unwrap_or_default(),
+            env: f.env.unwrap_or_default(),
+            cwd: f.cwd,
+            replace: f.replace.unwrap_or_default(),
+            options: f.options,
+        }
+    }
+}
+
+impl From<Profile> for ProfileFile {
+    fn from(p: Profile) -> ProfileFile {
+        fn list(v: Vec<String>) -> Option<Vec<String>> {
+            Some(v).filter(|v| !v.is_empty())
+        }
+        ProfileFile {
+            comment: p.comment,
+            extends: list(p.extends),
+            filter: list(p.filter),
+            filter_case_insensitive: list(p.filter_case_insensitive),
+            highlight: list(p.highlight).map(|h| h.into_iter().map(HighlightFile::Rule).collect()),
+            message: list(p.message),
+            message_case_insensitive: list(p.message_case_insensitive),
+            tag: list(p.tag),
+            tag_case_insensitive: list(p.tag_case_insensitive),
+            trigger: list(p.trigger),
+            env: Some(p.env).filter(|e| !e.is_empty()),
+            cwd: p.cwd,
+            replace: list(p.replace),
+            options: p.options,
+            unknown: HashMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// Clear the list `name` e.g before merging into a profile that replaces it
+    fn clear(&mut self, name: &str) -> Result<(), Error> {
+        match name {
+            "filter" => self.filter.clear(),
+            "filter_case_insensitive" => self.filter_case_insensitive.clear(),
+            "highlight" => self.highlight.clear(),
+            "message" => self.message.clear(),
+            "message_case_insensitive" => self.message_case_insensitive.clear(),
+            "tag" => self.tag.clear(),
+            "tag_case_insensitive" => self.tag_case_insensitive.clear(),
+            "trigger" => self.trigger.clear(),
+            "env" => self.env.clear(),
+            "input" => self.options.input = None,
+            "buffer" => self.options.buffer = None,
+            "package" => self.options.package = None,
+            _ => return Err(format_err!("{} is not a list", name)),
+        }
+        Ok(())
+    }
+}
+
+/// Append the items of `other` that are not in `list` keeping their order
+fn append_new<T: PartialEq>(list: &mut Vec<T>, other: Vec<T>) {
+    for item in other {
+        if !list.contains(&item) {
+            list.push(item);
         }
     }
 }
@@ -181,8 +969,216 @@ impl AddAssign for Profile {
         }
 
         vec_extend!(self.extends, other.extends);
-        vec_extend!(self.highlight, other.highlight);
+        vec_extend!(self.filter, other.filter);
+        vec_extend!(self.filter_case_insensitive, other.filter_case_insensitive);
+        // Highlights stack with later rules on top: keep the rules of the
+        // profile that takes precedence last
+        let highlight = std::mem::replace(&mut self.highlight, other.highlight);
+        append_new(&mut self.highlight, highlight);
         vec_extend!(self.message, other.message);
+        vec_extend!(
+            self.message_case_insensitive,
+            other.message_case_insensitive
+        );
         vec_extend!(self.tag, other.tag);
+        vec_extend!(self.tag_case_insensitive, other.tag_case_insensitive);
+        append_new(&mut self.trigger, other.trigger);
+
+        // Settings of the extending profile take precedence
+        for (k, v) in other.env {
+            self.env.entry(k).or_insert(v);
+        }
+        if self.cwd.is_none() {
+            self.cwd = other.cwd;
+        }
+        self.options += other.options;
     }
 }
+
+#[test]
+fn test_expand() {
+    let profiles: ConfigurationFile = from_str(
+        r#"
+        [profile.a]
+        tag = ["a"]
+        tag_case_insensitive = ["a"]
+        filter = ["a"]
+        level = "info"
+        head = 10
+
+        [profile.b]
+        extends = ["a"]
+        replace = ["tag"]
+        tag = ["b"]
+        filter = ["b"]
+        head = 20
+
+        [profile.c]
+        extends = ["c"]
+        "#,
+    )
+    .unwrap();
+    let profiles = merge(&[Layer {
+        path: PathBuf::new(),
+        content: String::new(),
+        config: profiles,
+    }]);
+
+    let b = expand("b", &profiles, 0).unwrap();
+    assert_eq!(b.tag, vec!["b"]);
+    assert_eq!(b.tag_case_insensitive, vec!["a"]);
+    assert_eq!(b.filter, vec!["a", "b"]);
+    assert_eq!(b.options.level.as_deref(), Some("info"));
+    assert_eq!(b.options.head, Some(20));
+    assert!(expand("c", &profiles, 0).is_err());
+}
+
+#[test]
+fn test_merge_highlight() {
+    let mut profile = Profile {
+        highlight: vec!["FATAL => bold red".into(), "AL E => underline blue".into()],
+        ..Default::default()
+    };
+    profile += Profile {
+        highlight: vec!["Z => bold".into(), "FATAL => bold red".into()],
+        ..Default::default()
+    };
+    assert_eq!(
+        profile.highlight,
+        vec!["Z => bold", "FATAL => bold red", "AL E => underline blue"]
+    );
+}
+
+#[test]
+fn test_merge_trigger() {
+    let mut profile = Profile {
+        trigger: vec!["-l E => exit 2".into(), "-l D => count 1".into()],
+        ..Default::default()
+    };
+    profile += Profile {
+        trigger: vec!["-l A => exit 3".into(), "-l E => exit 2".into()],
+        ..Default::default()
+    };
+    assert_eq!(
+        profile.trigger,
+        vec!["-l E => exit 2", "-l D => count 1", "-l A => exit 3"]
+    );
+}
+
+#[test]
+fn test_apply() {
+    let apply = |argv: &[&str], options: Options| {
+        let argv = argv.iter().map(OsString::from).collect::<Vec<_>>();
+        let args = cli::cli().get_matches_from(argv.clone());
+        let profile = Profile {
+            options,
+            ..Default::default()
+        };
+        apply_to(argv, &[], args, &profile)
+    };
+    let options = Options {
+        command: Some(vec!["echo".into(), "a b".into()]),
+        head: Some(10),
+        color: Some("never".into()),
+        overwrite: Some(true),
+        ..Default::default()
+    };
+
+    let args = apply(&["rogcat", "-t", "foo"], options.clone()).unwrap();
+    assert_eq!(args.value_of("COMMAND"), Some("echo 'a b'"));
+    assert_eq!(args.value_of("head"), Some("10"));
+    assert_eq!(args.value_of("color"), Some("never"));
+    assert!(!args.is_present("overwrite"));
+
+    let args = apply(&["rogcat", "-H", "5", "-o", "out", "cmd"], options).unwrap();
+    assert_eq!(args.value_of("COMMAND"), Some("cmd"));
+    assert_eq!(args.value_of("head"), Some("5"));
+    assert!(!args.is_present("color"));
+    assert!(args.is_present("overwrite"));
+
+    let options = Options {
+        restart: Some(true),
+        dump: Some(true),
+        ..Default::default()
+    };
+    assert!(apply(&["rogcat"], options).is_err());
+
+    // Subcommands skip the options they do not know
+    let argv = ["rogcat", "stats", "-i", "stats"]
+        .iter()
+        .map(OsString::from)
+        .collect::<Vec<_>>();
+    let args = cli::cli().get_matches_from(argv.clone());
+    let profile = Profile {
+        options: Options {
+            level: Some("warn".into()),
+            head: Some(10),
+            command: Some(vec!["echo".into()]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let stats = args.subcommand_matches("stats").cloned().unwrap();
+    let args = apply_to(argv, &["stats"], stats, &profile).unwrap();
+    assert_eq!(args.value_of("level"), Some("warn"));
+    assert_eq!(args.value_of("input"), Some("stats"));
+}
+
+#[test]
+fn test_validate() {
+    let content = r#"
+[profile.a]
+tag = ["(a"]
+trigger = ["=> reboot"]
+
+[profile."b c"]
+extends = ["a"]
+"#;
+    assert_eq!(line_of(content, "a", "tag"), Some(3));
+    assert_eq!(line_of(content, "a", "level"), Some(2));
+    assert_eq!(line_of(content, "b c", "extends"), Some(7));
+    assert_eq!(line_of(content, "d", "tag"), None);
+
+    let profiles = merge(&[Layer {
+        path: PathBuf::new(),
+        content: content.to_owned(),
+        config: from_str(content).unwrap(),
+    }]);
+    let fields = check(&profiles["a"])
+        .into_iter()
+        .map(|(f, _)| f)
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["tag", "trigger"]);
+
+    let mut profiles = profiles;
+    profiles.get_mut("a").unwrap().extends = vec!["b c".into()];
+    let mut stack = Vec::new();
+    assert!(cycle("a", &profiles, &mut stack));
+    assert_eq!(stack, vec!["a", "b c", "a"]);
+}
+
+#[test]
+fn test_layers() {
+    let dir = crate::utils::TempDir::new("profiles");
+    let dir = dir.path();
+    fs::create_dir_all(dir.join("shared")).unwrap();
+    let project = dir.join(PROJECT_FILE);
+    fs::write(
+        &project,
+        "include = [\"shared/common.toml\"]\n[profile.a]\ntag = [\"a\"]\nhead = 1\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("shared/common.toml"),
+        "include = [\"../.rogcat.toml\"]\n[profile.a]\ntag = [\"common\"]\nlevel = \"warn\"\nhead = 2\n",
+    )
+    .unwrap();
+
+    let mut layers = Vec::new();
+    load(&project, &mut layers, &mut Vec::new()).unwrap();
+    assert_eq!(layers.len(), 2);
+    let a = &merge(&layers)["a"];
+    assert_eq!(a.tag, vec!["a", "common"]);
+    assert_eq!(a.options.head, Some(1));
+    assert_eq!(a.options.level.as_deref(), Some("warn"));
+}
@@ -19,42 +19,201 @@
 // SOFTWARE.
 
 use crate::{
+    adb,
     lossy_lines::{lossy_lines, LossyLinesCodec},
-    utils::{adb, config_get},
+    profiles::Profile,
+    sqlite,
+    utils::{self, config_get},
     LogStream, StreamData, DEFAULT_BUFFER,
 };
 use clap::{value_t, ArgMatches};
 use failure::{err_msg, format_err, Error};
-use futures::{stream::iter_ok, Async, Future, Stream};
-use rogcat::record::Level;
-#[cfg(target_os = "linux")]
-use rogcat::record::{Record, Timestamp};
+use futures::{future::ok, stream::iter_ok, sync::oneshot, try_ready, Async, Future, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::{
+    parser,
+    record::{Level, Output, Record, Timestamp},
+};
 use std::{
     borrow::ToOwned,
+    cmp::min,
+    collections::HashMap,
     convert::Into,
     io::BufReader,
     net::ToSocketAddrs,
     path::PathBuf,
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 use tokio::{
     codec::{Decoder, FramedRead},
     fs::File,
+    io::{write_all, AsyncWrite},
     net::TcpStream,
+    reactor::Handle,
+    timer::Delay,
 };
 use tokio_process::{Child, CommandExt};
 use url::Url;
 
+/// Initial delay before a terminated cmd is respawned
+const BACKOFF_MIN: Duration = Duration::from_millis(250);
+/// Maximum delay between two respawn attempts
+const BACKOFF_MAX: Duration = Duration::from_secs(8);
+
+lazy_static! {
+    static ref LOGCAT_TIMESTAMP: Regex = Regex::new(r"^\d\d-\d\d \d\d:\d\d:\d\d\.\d{3}").unwrap();
+}
+
 /// A spawned child process that implements LogStream
 struct Process {
     cmd: Vec<String>,
+    /// Additional environment of cmd
+    env: HashMap<String, String>,
+    /// Working directory of cmd
+    cwd: Option<PathBuf>,
+    /// Run logcat on a device instead of cmd
+    logcat: Option<Logcat>,
     /// Respawn cmd upone termination
     respawn: bool,
     child: Option<Child>,
     stream: Option<LogStream>,
+    /// Terminated child whose exit status is not reported yet
+    exiting: Option<Child>,
+    /// Terminated without respawn
+    done: bool,
+    /// Pending respawn
+    delay: Option<Delay>,
+    backoff: Duration,
+    /// Pending connection to the adb server
+    connecting: Option<oneshot::Receiver<Connection>>,
+}
+
+/// Result of connecting logcat via the adb server. No stream if the adb
+/// server is not reachable.
+struct Connection {
+    boot_id: Option<String>,
+    stream: Option<std::net::TcpStream>,
+}
+
+/// Logcat on the device selected by serial. Keeps track of the last
+/// timestamp seen in order to resume after a reconnect.
+struct Logcat {
+    adb: String,
+    serial: Option<String>,
+    args: Vec<String>,
+    /// Value passed to -T
+    since: Option<String>,
+    /// Lines received with timestamp since
+    seen: Vec<String>,
+    /// Boot id of the device at the last start
+    boot_id: Option<String>,
+    /// Connected at least once
+    connected: bool,
+}
+
+impl Logcat {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["logcat".to_owned()];
+        args.extend(self.args.iter().cloned());
+        if let Some(ref since) = self.since {
+            args.push("-T".into());
+            args.push(since.clone());
+        }
+        args
+    }
+
+    /// Service for the adb server
+    fn service(&self) -> String {
+        let args = self
+            .args()
+            .iter()
+            .map(|a| adb::quote(a))
+            .collect::<Vec<_>>();
+        format!("exec:{}", args.join(" "))
+    }
+
+    /// Command line for the adb binary
+    fn cmd(&self) -> Vec<String> {
+        let mut cmd = vec![self.adb.clone()];
+        if let Some(ref serial) = self.serial {
+            cmd.push("-s".into());
+            cmd.push(serial.clone());
+        }
+        cmd.extend(self.args());
+        cmd
+    }
+
+    /// Connect to the adb server on a thread. Reconnects wait for the device
+    /// unless the adb server is not reachable which is left to adb.
+    fn connect(&self) -> oneshot::Receiver<Connection> {
+        let serial = self.serial.clone();
+        let service = self.service();
+        let wait = self.connected;
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            let client = adb::Client::default();
+            if wait {
+                client.wait_for_device(serial.as_deref()).ok();
+            }
+            let boot_id = query_boot_id(serial.as_deref());
+            let stream = client.open(serial.as_deref(), &service).ok();
+            tx.send(Connection { boot_id, stream }).ok();
+        });
+        rx
+    }
+
+    /// Remember the timestamp of line. Returns None if the line has no
+    /// timestamp and Some(false) if it was received before a reconnect.
+    fn track(&mut self, line: &str) -> Option<bool> {
+        let timestamp = LOGCAT_TIMESTAMP.find(line)?.as_str();
+        if self.since.as_deref() == Some(timestamp) {
+            if self.seen.iter().any(|l| l == line) {
+                return Some(false);
+            }
+        } else {
+            self.since = Some(timestamp.to_owned());
+            self.seen.clear();
+        }
+        self.seen.push(line.to_owned());
+        Some(true)
+    }
+}
+
+fn query_boot_id(serial: Option<&str>) -> Option<String> {
+    utils::shell(serial, &["cat", "/proc/sys/kernel/random/boot_id"])
+        .ok()
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+}
+
+/// Exit code of a process. Processes killed by a signal exit with 128 + signal
+/// like in a shell.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
 }
 
-/// Open a file and provide a stream of lines
+/// Synthetic record inserted into the stream
+pub fn marker(text: &str) -> Record {
+    let message = format!("--- {text} ---");
+    Record {
+        timestamp: Some(Timestamp::new(time::now())),
+        raw: message.clone(),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Open files and provide a stream of lines. SQLite databases provide records.
 pub fn files(args: &ArgMatches) -> Result<LogStream, Error> {
     let files = args
         .values_of("input")
@@ -62,13 +221,20 @@ pub fn files(args: &ArgMatches) -> Result<LogStream, Error> {
         .map(PathBuf::from)
         .collect::<Vec<PathBuf>>();
 
+    let fts = args.value_of("fts").map(ToOwned::to_owned);
+    let condition = args.value_of("where").map(ToOwned::to_owned);
     let f = iter_ok::<_, Error>(files)
-        .map(|f| {
-            File::open(f.clone())
-                .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
-                .flatten_stream()
-                .map(StreamData::Line)
-                .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e))
+        .map(move |f| -> LogStream {
+            if sqlite::is_database(&f) {
+                return sqlite::records(f, fts.clone(), condition.clone());
+            }
+            Box::new(
+                File::open(f.clone())
+                    .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
+                    .flatten_stream()
+                    .map(StreamData::Line)
+                    .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e)),
+            )
         })
         .flatten();
 
@@ -83,6 +249,25 @@ pub fn stdin() -> LogStream {
     Box::new(s)
 }
 
+/// Source of subcommands: stdin with `-i -`, files with `-i` or logcat
+pub fn source(args: &ArgMatches) -> Result<LogStream, Error> {
+    match args.values_of("input").map(|i| i.collect::<Vec<_>>()) {
+        Some(input) if input == ["-"] => Ok(stdin()),
+        Some(_) => files(args),
+        None => logcat(args),
+    }
+}
+
+/// Future that resolves on the first Ctrl-C. Used to stop live sources and
+/// print the results collected so far.
+pub fn interrupted() -> impl Future<Item = (), Error = ()> {
+    tokio_signal::ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|_| ())
+}
+
 /// Open a serial port and provide a stream of lines
 pub fn serial(_args: &ArgMatches) -> LogStream {
     unimplemented!()
@@ -123,13 +308,16 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
     Ok(Box::new(stream))
 }
 
-/// Connect to tcp socket and profile a stream of lines
-pub fn tcp(addr: &Url) -> Result<LogStream, Error> {
+/// Connect to tcp socket and profile a stream of lines. The optional filter
+/// is sent to rogcat servers upon connect.
+pub fn tcp(addr: &Url, filter: Option<&str>) -> Result<LogStream, Error> {
     let addr = addr
         .to_socket_addrs()?
         .next()
         .ok_or_else(|| err_msg("Failed to parse addr"))?;
+    let filter = filter.map(|f| format!("{f}\n"));
     let s = TcpStream::connect(&addr)
+        .and_then(move |s| send_filter(s, filter))
         .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
         .flatten_stream()
         .map_err(|e| format_err!("Failed to connect: {}", e))
@@ -138,17 +326,41 @@ pub fn tcp(addr: &Url) -> Result<LogStream, Error> {
     Ok(Box::new(s))
 }
 
-/// Start logcat
-pub fn logcat(args: &ArgMatches) -> Result<LogStream, Error> {
-    let mut cmd = vec![adb()?.display().to_string()];
+/// Connect to a unix socket and provide a stream of lines
+#[cfg(unix)]
+pub fn unix(addr: &Url, filter: Option<&str>) -> Result<LogStream, Error> {
+    let filter = filter.map(|f| format!("{f}\n"));
+    let s = tokio::net::UnixStream::connect(addr.path())
+        .and_then(move |s| send_filter(s, filter))
+        .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
+        .flatten_stream()
+        .map_err(|e| format_err!("Failed to connect: {}", e))
+        .map(StreamData::Line);
 
-    if args.is_present("dev") {
-        let device = value_t!(args, "dev", String).unwrap_or_else(|e| e.exit());
-        cmd.push("-s".into());
-        cmd.push(device);
+    Ok(Box::new(s))
+}
+
+/// Write the filter line if any
+fn send_filter<S>(
+    stream: S,
+    filter: Option<String>,
+) -> Box<dyn Future<Item = S, Error = std::io::Error> + Send>
+where
+    S: AsyncWrite + Send + 'static,
+{
+    match filter {
+        Some(filter) => Box::new(write_all(stream, filter).map(|(s, _)| s)),
+        None => Box::new(ok(stream)),
     }
+}
 
-    cmd.push("logcat".into());
+/// Start logcat
+pub fn logcat(args: &ArgMatches) -> Result<LogStream, Error> {
+    if args.is_present("since") {
+        return Err(err_msg("--since is only supported with --ffx"));
+    }
+    let serial = args.value_of("dev").map(ToOwned::to_owned);
+    let mut cmd = Vec::new();
     let mut respawn = args.is_present("restart") | config_get::<bool>("restart").unwrap_or(true);
 
     if args.is_present("tail") {
@@ -178,12 +390,38 @@ pub fn logcat(args: &ArgMatches) -> Result<LogStream, Error> {
         cmd.push(buffer);
     }
 
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    // The adb binary is only needed if the server cannot be reached
+    let adb = utils::adb()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "adb".into());
+    let logcat = Logcat {
+        adb,
+        serial,
+        args: cmd,
+        since: None,
+        seen: Vec::new(),
+        boot_id: None,
+        connected: false,
+    };
+
+    let mut process = Process::with_cmd(logcat.cmd(), respawn);
+    process.logcat = Some(logcat);
+    Ok(Box::new(process))
 }
 
 /// Start ffx log
 pub fn fuchsia(args: &ArgMatches) -> Result<LogStream, Error> {
-    let mut cmd = vec!["ffx", "log", "--no-color"];
+    let mut cmd = vec!["ffx"];
+
+    if let Some(target) = args.value_of("dev") {
+        cmd.extend(["--target", target]);
+    }
+
+    cmd.extend(["log", "--no-color"]);
+
+    if let Some(since) = args.value_of("since") {
+        cmd.extend(["--since", since]);
+    }
 
     if args.is_present("dump") {
         cmd.push("--dump");
@@ -206,51 +444,246 @@ pub fn fuchsia(args: &ArgMatches) -> Result<LogStream, Error> {
 
     let cmd = cmd.iter().map(ToString::to_string).collect();
 
-    Ok(Box::new(Process::with_cmd(cmd, false)))
+    Ok(Box::new(FuchsiaLines::new(Process::with_cmd(cmd, false))))
+}
+
+/// Time to wait for continuation lines before a pending record is passed on
+const FUCHSIA_CONTINUATION: Duration = Duration::from_millis(50);
+
+/// Joins lines of multi-line `ffx log` messages into a single line
+struct FuchsiaLines<S> {
+    inner: S,
+    pending: Option<String>,
+    /// Passes on the pending record if no more lines arrive
+    flush: Option<Delay>,
+    done: bool,
+}
+
+impl<S> FuchsiaLines<S> {
+    fn new(inner: S) -> FuchsiaLines<S> {
+        FuchsiaLines {
+            inner,
+            pending: None,
+            flush: None,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = StreamData, Error = Error>> Stream for FuchsiaLines<S> {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if self.done {
+            return Ok(Async::Ready(self.pending.take().map(StreamData::Line)));
+        }
+
+        loop {
+            match self.inner.poll()? {
+                Async::Ready(Some(StreamData::Line(line))) => {
+                    self.flush = None;
+                    match self.pending {
+                        Some(ref mut pending) if !parser::is_fuchsia_record(&line) => {
+                            pending.push('\n');
+                            pending.push_str(&line);
+                        }
+                        _ => {
+                            if let Some(pending) = self.pending.replace(line) {
+                                return Ok(Async::Ready(Some(StreamData::Line(pending))));
+                            }
+                        }
+                    }
+                }
+                Async::Ready(Some(data)) => return Ok(Async::Ready(Some(data))),
+                Async::Ready(None) => {
+                    self.done = true;
+                    return Ok(Async::Ready(self.pending.take().map(StreamData::Line)));
+                }
+                // Don't hold back the pending record until the next one
+                // arrives but give continuation lines a moment
+                Async::NotReady if self.pending.is_some() => {
+                    let flush = self
+                        .flush
+                        .get_or_insert_with(|| Delay::new(Instant::now() + FUCHSIA_CONTINUATION));
+                    try_ready!(flush
+                        .poll()
+                        .map_err(|e| format_err!("Failed to wait for ffx log: {}", e)));
+                    self.flush = None;
+                    return Ok(Async::Ready(self.pending.take().map(StreamData::Line)));
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
 }
 
 /// Start a process and stream it stdout
-pub fn process(args: &ArgMatches) -> Result<LogStream, Error> {
+pub fn process(args: &ArgMatches, profile: &Profile) -> Result<LogStream, Error> {
     let respawn = args.is_present("restart");
-    let cmd = value_t!(args, "COMMAND", String)?
-        .split_whitespace()
-        .map(ToOwned::to_owned)
-        .collect();
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    let command = args
+        .values_of("COMMAND")
+        .ok_or_else(|| err_msg("Missing command"))?
+        .collect::<Vec<_>>();
+
+    let cmd = if args.is_present("shell") {
+        // Keep the quoting of "-- cmd args..."
+        let command = if command.len() == 1 {
+            command[0].to_owned()
+        } else {
+            shell_words::join(&command)
+        };
+        if cfg!(windows) {
+            vec!["cmd".into(), "/C".into(), command]
+        } else {
+            vec!["sh".into(), "-c".into(), command]
+        }
+    } else if command.len() == 1 {
+        shell_words::split(command[0])
+            .map_err(|e| format_err!("Failed to parse command \"{}\": {}", command[0], e))?
+    } else {
+        command.iter().map(|&c| c.to_owned()).collect()
+    };
+
+    if cmd.is_empty() {
+        return Err(err_msg("Empty command"));
+    }
+
+    let mut process = Process::with_cmd(cmd, respawn);
+    process.env = profile.env.clone();
+    process.cwd = profile.cwd.clone();
+    Ok(Box::new(process))
 }
 
 impl Process {
     fn with_cmd(cmd: Vec<String>, respawn: bool) -> Process {
         Process {
             cmd,
+            env: HashMap::new(),
+            cwd: None,
+            logcat: None,
             respawn,
             child: None,
             stream: None,
+            exiting: None,
+            done: false,
+            delay: None,
+            backoff: BACKOFF_MIN,
+            connecting: None,
+        }
+    }
+
+    /// Connect logcat to the adb server or spawn cmd
+    fn start(&mut self) -> Result<(), Error> {
+        match self.logcat {
+            Some(ref logcat) => {
+                self.connecting = Some(logcat.connect());
+                Ok(())
+            }
+            None => self.spawn(),
         }
     }
 
-    fn spawn(&mut self) -> Result<Async<Option<StreamData>>, Error> {
-        let mut child = Command::new(self.cmd[0].clone())
+    /// Use the logcat connection or fall back to the adb binary. Reconnects
+    /// are reported with a marker.
+    fn connected(&mut self, connection: Connection) -> Result<Option<StreamData>, Error> {
+        let logcat = self.logcat.as_mut().expect("Missing logcat");
+        let marker = if logcat.connected {
+            let rebooted = logcat.boot_id.is_some()
+                && connection.boot_id.is_some()
+                && logcat.boot_id != connection.boot_id;
+            Some(StreamData::Record(marker(if rebooted {
+                "device rebooted"
+            } else {
+                "device reconnected"
+            })))
+        } else {
+            None
+        };
+        logcat.connected = true;
+        logcat.boot_id = connection.boot_id;
+
+        match connection.stream {
+            Some(stream) => {
+                let stream = TcpStream::from_std(stream, &Handle::default())?;
+                let stream = Decoder::framed(LossyLinesCodec::new(), stream)
+                    .map_err(Into::into)
+                    .map(StreamData::Line);
+                self.stream = Some(Box::new(stream));
+            }
+            None => {
+                self.cmd = logcat.cmd();
+                self.spawn()?;
+            }
+        }
+        Ok(marker)
+    }
+
+    /// Spawn cmd
+    fn spawn(&mut self) -> Result<(), Error> {
+        let mut command = Command::new(&self.cmd[0]);
+        command
             .args(&self.cmd[1..])
+            .envs(&self.env)
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn_async()?;
+            .stderr(Stdio::piped());
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+        let mut child = command
+            .spawn_async()
+            .map_err(|e| format_err!("Failed to run {}: {}", self.cmd[0], e))?;
 
         let stdout = BufReader::new(child.stdout().take().unwrap());
         let stderr = BufReader::new(child.stderr().take().unwrap());
         self.child = Some(child);
 
-        let stdout = lossy_lines(stdout)
-            .map_err(Into::into)
-            .map(StreamData::Line);
-        let stderr = lossy_lines(stderr)
-            .map_err(Into::into)
-            .map(StreamData::Line);
-
-        let mut stream = stdout.select(stderr);
-        let poll = stream.poll();
-        self.stream = Some(Box::new(stream));
-        poll
+        // Tag the output of commands but not of adb
+        let tag = self.logcat.is_none();
+        let stdout = lossy_lines(stdout).map_err(Into::into).map(move |l| {
+            if tag {
+                StreamData::Output(Output::Stdout, l)
+            } else {
+                StreamData::Line(l)
+            }
+        });
+        let stderr = lossy_lines(stderr).map_err(Into::into).map(move |l| {
+            if tag {
+                StreamData::Output(Output::Stderr, l)
+            } else {
+                StreamData::Line(l)
+            }
+        });
+
+        self.stream = Some(Box::new(stdout.select(stderr)));
+        Ok(())
+    }
+
+    /// Record that reports the exit status of cmd
+    fn exit(&self, status: ExitStatus) -> StreamData {
+        let message = format!(
+            "--- {} exited with {} ---",
+            shell_words::join(&self.cmd),
+            status
+        );
+        let record = Record {
+            timestamp: Some(Timestamp::new(time::now())),
+            level: if status.success() {
+                Level::Info
+            } else {
+                Level::Error
+            },
+            raw: message.clone(),
+            message,
+            ..Default::default()
+        };
+        StreamData::Exit(exit_code(status), record)
+    }
+
+    /// Schedule the next respawn attempt and double the backoff
+    fn schedule(&mut self) {
+        self.delay = Some(Delay::new(Instant::now() + self.backoff));
+        self.backoff = min(self.backoff * 2, BACKOFF_MAX);
     }
 }
 
@@ -259,13 +692,122 @@ impl Stream for Process {
     type Error = Error;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        if let Some(ref mut inner) = self.stream {
-            match inner.poll() {
-                Ok(Async::Ready(None)) if self.respawn => self.spawn(),
-                poll => poll,
+        loop {
+            if let Some(ref mut child) = self.exiting {
+                let status = try_ready!(child.poll());
+                self.exiting = None;
+                return Ok(Async::Ready(Some(self.exit(status))));
+            }
+
+            if let Some(ref mut delay) = self.delay {
+                try_ready!(delay.poll().map_err(|e| format_err!("Timer error: {}", e)));
+                self.delay = None;
+                self.start()?;
+            }
+
+            if let Some(ref mut connecting) = self.connecting {
+                let connection = try_ready!(connecting
+                    .poll()
+                    .map_err(|_| err_msg("Failed to connect to adb")));
+                self.connecting = None;
+                match self.connected(connection)? {
+                    Some(marker) => return Ok(Async::Ready(Some(marker))),
+                    None => continue,
+                }
+            }
+
+            if self.stream.is_none() {
+                if self.done {
+                    return Ok(Async::Ready(None));
+                }
+                self.start()?;
+                continue;
+            }
+
+            let inner = self.stream.as_mut().expect("Missing stream");
+            match inner.poll()? {
+                Async::Ready(None) => {
+                    self.stream = None;
+                    let child = self.child.take();
+                    if self.logcat.is_none() {
+                        self.exiting = child;
+                    }
+                    if !self.respawn {
+                        self.done = true;
+                    } else {
+                        self.schedule();
+                        if self.logcat.is_some() {
+                            return Ok(Async::Ready(Some(StreamData::Record(marker(
+                                "device disconnected",
+                            )))));
+                        }
+                    }
+                }
+                Async::Ready(Some(StreamData::Line(line))) => {
+                    // Errors of adb e.g "no devices" do not count as progress
+                    match self.logcat {
+                        Some(ref mut logcat) => match logcat.track(&line) {
+                            Some(false) => continue,
+                            Some(true) => self.backoff = BACKOFF_MIN,
+                            None => (),
+                        },
+                        None => self.backoff = BACKOFF_MIN,
+                    }
+                    return Ok(Async::Ready(Some(StreamData::Line(line))));
+                }
+                poll => return Ok(poll),
             }
-        } else {
-            self.spawn()
         }
     }
 }
+
+#[test]
+fn test_logcat_track() {
+    let mut logcat = Logcat {
+        adb: "adb".into(),
+        serial: None,
+        args: Vec::new(),
+        since: None,
+        seen: Vec::new(),
+        boot_id: None,
+        connected: false,
+    };
+    let a = "01-02 03:04:05.678  1234  1235 I Tag: a";
+    let b = "01-02 03:04:05.678  1234  1235 I Tag: b";
+    assert_eq!(logcat.track("--------- beginning of main"), None);
+    assert_eq!(logcat.track(a), Some(true));
+    assert_eq!(logcat.since.as_deref(), Some("01-02 03:04:05.678"));
+    assert!(logcat
+        .args()
+        .ends_with(&["-T".into(), "01-02 03:04:05.678".into()]));
+    // Resumed with -T: a is a duplicate, b is new
+    assert_eq!(logcat.track(a), Some(false));
+    assert_eq!(logcat.track(b), Some(true));
+}
+
+#[test]
+fn test_fuchsia_lines() {
+    let lines = [
+        "[00001.000000][klog] ERROR: panic:",
+        "  frame 0",
+        "  frame 1",
+        "[00002.000000][klog] INFO: done",
+    ]
+    .iter()
+    .map(|l| StreamData::Line((*l).to_owned()))
+    .collect::<Vec<_>>();
+    let lines = FuchsiaLines::new(iter_ok::<_, Error>(lines))
+        .wait()
+        .map(|l| match l.unwrap() {
+            StreamData::Line(l) => l,
+            _ => panic!("Unexpected stream data"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            "[00001.000000][klog] ERROR: panic:\n  frame 0\n  frame 1",
+            "[00002.000000][klog] INFO: done",
+        ]
+    );
+}
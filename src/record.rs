@@ -41,6 +41,7 @@ pub enum Format {
     Human,
     Json,
     Raw,
+    Sqlite,
 }
 
 impl Format {
@@ -48,7 +49,7 @@ impl Format {
         match self {
             Format::Csv => {
                 let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
-                wtr.serialize(record)?;
+                wtr.serialize(CsvRecord::from(record.clone()))?;
                 wtr.flush()?;
                 Ok(String::from_utf8(wtr.into_inner().unwrap())?
                     .trim_end_matches('\n')
@@ -59,6 +60,7 @@ impl Format {
             Format::Json => serde_json::to_string(record)
                 .map_err(|e| format_err!("Json serialization error: {}", e)),
             Format::Raw => Ok(record.raw.clone()),
+            Format::Sqlite => Err(format_err!("SQLite records are not formatted as lines")),
         }
     }
 }
@@ -72,6 +74,7 @@ impl FromStr for Format {
             "human" => Ok(Format::Human),
             "json" => Ok(Format::Json),
             "raw" => Ok(Format::Raw),
+            "sqlite" => Ok(Format::Sqlite),
             _ => Err("Format parsing error"),
         }
     }
@@ -88,6 +91,7 @@ impl Display for Format {
                 Format::Human => "human",
                 Format::Json => "json",
                 Format::Raw => "raw",
+                Format::Sqlite => "sqlite",
             }
         )
     }
@@ -131,6 +135,7 @@ impl Display for Level {
     }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a> From<&'a str> for Level {
     fn from(s: &str) -> Self {
         match s {
@@ -153,6 +158,27 @@ impl Level {
     }
 }
 
+/// Output stream of a spawned command
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Output::Stdout => "stdout",
+                Output::Stderr => "stderr",
+            }
+        )
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Timestamp {
     pub tm: Tm,
@@ -235,4 +261,78 @@ pub struct Record {
     pub process: String,
     pub thread: String,
     pub raw: String,
+    /// Source location (`file:line`) if provided by the log format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Output stream if the record was read from a spawned command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+    /// Seconds since the first, previous or marker record if computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed: Option<f64>,
+    /// Set if the record follows a gap to the previous record
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gap: bool,
+}
+
+/// Shadow of `Record` for csv with the tags joined into a single column
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct CsvRecord {
+    timestamp: Option<Timestamp>,
+    message: String,
+    level: Level,
+    tag: String,
+    process: String,
+    thread: String,
+    raw: String,
+    #[serde(default)]
+    location: Option<String>,
+    #[serde(default)]
+    output: Option<Output>,
+    /// Always written in order to keep the number of columns constant
+    #[serde(default)]
+    elapsed: Option<f64>,
+    #[serde(default)]
+    gap: bool,
+}
+
+impl From<Record> for CsvRecord {
+    fn from(record: Record) -> CsvRecord {
+        CsvRecord {
+            timestamp: record.timestamp,
+            message: record.message,
+            level: record.level,
+            tag: record.tags.join(","),
+            process: record.process,
+            thread: record.thread,
+            raw: record.raw,
+            location: record.location,
+            output: record.output,
+            elapsed: record.elapsed,
+            gap: record.gap,
+        }
+    }
+}
+
+impl From<CsvRecord> for Record {
+    fn from(record: CsvRecord) -> Record {
+        let tags = if record.tag.is_empty() {
+            Vec::new()
+        } else {
+            record.tag.split(',').map(|s| s.trim().to_owned()).collect()
+        };
+        Record {
+            timestamp: record.timestamp,
+            message: record.message,
+            level: record.level,
+            tags,
+            process: record.process,
+            thread: record.thread,
+            raw: record.raw,
+            location: record.location,
+            output: record.output,
+            elapsed: record.elapsed,
+            gap: record.gap,
+        }
+    }
 }
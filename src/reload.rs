@@ -0,0 +1,250 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    filter::{self, Filter},
+    highlight::{self, Highlight},
+    pager, profiles, reader, utils,
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{stream, Async, Poll, Stream};
+use rogcat::record::Record;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::timer::Interval;
+
+/// Interval of the checks for modified files
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Hangup = Box<dyn Stream<Item = (), Error = Error> + Send>;
+
+/// Stream of SIGHUP signals. SIGHUP keeps terminating rogcat if the output
+/// does not go to a terminal e.g with `-o` when the terminal is closed.
+#[cfg(unix)]
+fn hangup() -> Hangup {
+    use futures::Future;
+    use tokio_signal::unix::{Signal, SIGHUP};
+
+    if !atty::is(atty::Stream::Stdout) && !pager::is_active() {
+        return Box::new(stream::empty());
+    }
+    Box::new(
+        Signal::new(SIGHUP)
+            .flatten_stream()
+            .map(|_| ())
+            .map_err(|e| format_err!("Failed to receive SIGHUP: {}", e)),
+    )
+}
+
+#[cfg(not(unix))]
+fn hangup() -> Hangup {
+    Box::new(stream::empty())
+}
+
+/// Modification time of `path` or None if it does not exist
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Rebuild the filter and highlights when the profile files or the
+/// configuration file change or on SIGHUP. A marker record reports the
+/// reload. The current filter and highlights stay active if a file is invalid.
+pub struct Reload<S> {
+    inner: S,
+    /// Command line without the options of the profile
+    args: ArgMatches<'static>,
+    filter: Arc<Mutex<Filter>>,
+    highlight: Highlight,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    interval: Interval,
+    hangup: Hangup,
+}
+
+impl<S> Reload<S> {
+    pub fn new(
+        inner: S,
+        args: ArgMatches<'static>,
+        filter: Arc<Mutex<Filter>>,
+        highlight: Highlight,
+    ) -> Reload<S> {
+        let mut reload = Reload {
+            inner,
+            args,
+            filter,
+            highlight,
+            files: Vec::new(),
+            interval: Interval::new_interval(POLL_INTERVAL),
+            hangup: hangup(),
+        };
+        reload.watch();
+        reload
+    }
+
+    /// Update the list of watched files. Files of the last list are kept if
+    /// they cannot be resolved e.g because an include is invalid.
+    fn watch(&mut self) {
+        let mut paths = profiles::paths(&self.args);
+        paths.push(utils::config_file());
+        for (path, _) in &self.files {
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+        self.files = paths
+            .into_iter()
+            .map(|p| {
+                let modified = modified(&p);
+                (p, modified)
+            })
+            .collect();
+    }
+
+    fn changed(&self) -> bool {
+        self.files.iter().any(|(p, m)| modified(p) != *m)
+    }
+
+    fn reload(&mut self) -> Record {
+        let result = utils::config_reload().and_then(|_| {
+            let profile = profiles::from_args(&self.args)?;
+            let filter = filter::from_args_profile(&self.args, &profile)?;
+            let highlight = highlight::from_args_profile(&self.args, &profile)?;
+            Ok((filter, highlight))
+        });
+        self.watch();
+
+        match result {
+            Ok((filter, highlight)) => {
+                *self.filter.lock().expect("Failed to lock filter") = filter;
+                *self.highlight.lock().expect("Failed to lock highlight") = highlight;
+                reader::marker("Reloaded profiles")
+            }
+            Err(e) => {
+                // Regex errors span multiple lines
+                let e = e
+                    .to_string()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                reader::marker(&format!("Failed to reload profiles: {e}"))
+            }
+        }
+    }
+}
+
+impl<S> Stream for Reload<S>
+where
+    S: Stream<Item = Record, Error = Error>,
+{
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // Poll the signal and the interval until they are not ready to be
+        // notified again
+        let mut reload = false;
+        loop {
+            match self.hangup.poll() {
+                Ok(Async::Ready(Some(()))) => reload = true,
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(None)) | Err(_) => {
+                    self.hangup = Box::new(stream::empty());
+                    break;
+                }
+            }
+        }
+        while let Async::Ready(Some(_)) = self
+            .interval
+            .poll()
+            .map_err(|e| format_err!("Timer error: {}", e))?
+        {
+            reload |= self.changed();
+        }
+        if reload {
+            return Ok(Async::Ready(Some(self.reload())));
+        }
+        self.inner.poll()
+    }
+}
+
+#[test]
+fn test_reload() {
+    use std::time::UNIX_EPOCH;
+
+    let dir = utils::TempDir::new("reload");
+    let path = dir.path().join("profiles.toml");
+    let write = |content: &str, secs: u64| {
+        fs::write(&path, content).unwrap();
+        // Do not depend on the resolution of the file system timestamps
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    };
+    write("[profile.a]\ntag = [\"a\"]\n", 1);
+
+    let args = crate::cli::cli().get_matches_from(vec![
+        "rogcat".into(),
+        "-P".into(),
+        path.display().to_string(),
+        "-p".into(),
+        "a".into(),
+    ]);
+    let profile = profiles::from_args(&args).unwrap();
+    let filter = Arc::new(Mutex::new(
+        filter::from_args_profile(&args, &profile).unwrap(),
+    ));
+    let highlight = Arc::new(Mutex::new(Vec::new()));
+    let mut reload = Reload::new(
+        stream::empty::<Record, Error>(),
+        args,
+        filter.clone(),
+        highlight,
+    );
+    let record = |tag: &str| Record {
+        tags: vec![tag.to_owned()],
+        ..Default::default()
+    };
+    let passes = |tag| filter.lock().unwrap().filter(&record(tag));
+
+    assert!(!reload.changed());
+    assert!(passes("a"));
+    assert!(!passes("b"));
+
+    // An invalid file keeps the current filter
+    write("[profile.a]\ntag = [\"(\"]\n", 2);
+    assert!(reload.changed());
+    let marker = reload.reload();
+    assert!(marker.message.starts_with("--- Failed to reload profiles"));
+    assert!(!reload.changed());
+    assert!(passes("a"));
+
+    write("[profile.a]\ntag = [\"b\"]\n", 3);
+    assert!(reload.changed());
+    assert_eq!(reload.reload().message, "--- Reloaded profiles ---");
+    assert!(!passes("a"));
+    assert!(passes("b"));
+}
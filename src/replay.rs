@@ -0,0 +1,224 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use rogcat::record::Record;
+use std::time::{Duration, Instant};
+use time::Tm;
+use tokio::timer::Delay;
+
+/// Replay settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Speed factor. 2.0 replays twice as fast
+    pub speed: f64,
+    /// Gaps between two records are limited to this
+    pub max_gap: Option<Duration>,
+    /// Skip this duration at the start of the capture
+    pub start: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            speed: 1.0,
+            max_gap: None,
+            start: Duration::from_secs(0),
+        }
+    }
+}
+
+/// Replay config from args if replay is enabled
+pub fn from_args(args: &ArgMatches) -> Result<Option<Config>, Error> {
+    if !args.is_present("replay") {
+        return Ok(None);
+    }
+
+    let mut config = Config::default();
+    if let Some(speed) = args.value_of("replay-speed") {
+        config.speed = parse_speed(speed)?;
+    }
+    if let Some(max_gap) = args.value_of("replay-max-gap") {
+        config.max_gap = Some(parse_duration(max_gap)?);
+    }
+    if let Some(start) = args.value_of("replay-start") {
+        config.start = parse_duration(start)?;
+    }
+    Ok(Some(config))
+}
+
+/// Parse a speed factor like `2x`, `0.5x` or `2`
+pub fn parse_speed(s: &str) -> Result<f64, Error> {
+    let speed = s
+        .trim_end_matches(['x', 'X'])
+        .parse::<f64>()
+        .map_err(|e| format_err!("Invalid speed {}: {}", s, e))?;
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err(format_err!("Invalid speed {}: must be positive", s))
+    }
+}
+
+/// Parse a duration like `500ms`, `5s`, `1.5m` or `1h`. Plain numbers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .map_err(|e| format_err!("Invalid duration {}: {}", s, e))?;
+    let factor = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format_err!("Invalid duration {}: unknown unit {}", s, unit)),
+    };
+    Duration::try_from_secs_f64(value * factor)
+        .map_err(|_| format_err!("Invalid duration {}: out of range", s))
+}
+
+/// Emit records paced by the deltas of their timestamps. Records without
+/// timestamp are emitted right away.
+pub struct Replay<S> {
+    inner: S,
+    config: Config,
+    /// Latest timestamp seen so far
+    last: Option<Tm>,
+    /// Capture time since the first record with gaps limited to max_gap
+    elapsed: Duration,
+    /// Wall clock time of the first replayed record
+    started: Option<Instant>,
+    pending: Option<(Record, Delay)>,
+}
+
+impl<S> Replay<S> {
+    pub fn new(inner: S, config: Config) -> Replay<S> {
+        Replay {
+            inner,
+            config,
+            last: None,
+            elapsed: Duration::from_secs(0),
+            started: None,
+            pending: None,
+        }
+    }
+
+    /// Advance the capture time by the delta to `timestamp`. Records are not
+    /// necessarily in order and older records do not move the capture time.
+    fn advance(&mut self, timestamp: Tm) {
+        match self.last {
+            Some(last) if timestamp <= last => (),
+            Some(last) => {
+                let delta = (timestamp - last).to_std().unwrap_or_default();
+                let delta = match self.config.max_gap {
+                    Some(max_gap) => delta.min(max_gap),
+                    None => delta,
+                };
+                self.elapsed += delta;
+                self.last = Some(timestamp);
+            }
+            None => self.last = Some(timestamp),
+        }
+    }
+}
+
+impl<S> Stream for Replay<S>
+where
+    S: Stream<Item = Record, Error = Error>,
+{
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some((_, ref mut delay)) = self.pending {
+                try_ready!(delay.poll().map_err(|e| format_err!("Timer error: {}", e)));
+                let (record, _) = self.pending.take().expect("Missing pending record");
+                return Ok(Async::Ready(Some(record)));
+            }
+
+            let record = match try_ready!(self.inner.poll()) {
+                Some(record) => record,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let timestamp = match record.timestamp {
+                Some(ref timestamp) => timestamp.tm,
+                None => return Ok(Async::Ready(Some(record))),
+            };
+
+            self.advance(timestamp);
+            if self.elapsed < self.config.start {
+                continue;
+            }
+
+            let offset = (self.elapsed - self.config.start).as_secs_f64() / self.config.speed;
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let due = Duration::try_from_secs_f64(offset)
+                .ok()
+                .and_then(|offset| started.checked_add(offset))
+                .ok_or_else(|| err_msg("Replay delay is out of range, increase --replay-speed"))?;
+            if due <= Instant::now() {
+                return Ok(Async::Ready(Some(record)));
+            }
+            self.pending = Some((record, Delay::new(due)));
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse_speed("2x").unwrap(), 2.0);
+    assert_eq!(parse_speed("0.5").unwrap(), 0.5);
+    assert!(parse_speed("0x").is_err());
+    assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+    assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("3").unwrap(), Duration::from_secs(3));
+    assert!(parse_duration("3d").is_err());
+    assert!(parse_duration(&"9".repeat(400)).is_err());
+    assert!(parse_duration(&format!("{}h", "9".repeat(20))).is_err());
+}
+
+#[test]
+fn test_advance() {
+    let t = |s| time::strptime(s, "%m-%d %H:%M:%S").unwrap();
+    let config = Config {
+        max_gap: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut replay = Replay::new((), config);
+    replay.advance(t("01-01 00:00:00"));
+    replay.advance(t("01-01 00:00:05"));
+    assert_eq!(replay.elapsed, Duration::from_secs(5));
+    // Gap limited to 10s
+    replay.advance(t("01-01 01:00:00"));
+    assert_eq!(replay.elapsed, Duration::from_secs(15));
+    // Backwards jumps do not count
+    replay.advance(t("01-01 00:30:00"));
+    assert_eq!(replay.elapsed, Duration::from_secs(15));
+    // and do not rewind the reference
+    replay.advance(t("01-01 01:00:01"));
+    assert_eq!(replay.elapsed, Duration::from_secs(16));
+}
@@ -0,0 +1,302 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    filter::{self, Filter},
+    reader::marker,
+};
+use failure::{format_err, Error};
+use futures::{sync::mpsc, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use rogcat::record::{Format, Record};
+use std::{
+    io,
+    net::ToSocketAddrs,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    codec::{FramedRead, FramedWrite, LinesCodec},
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use url::Url;
+
+/// Default number of lines queued per client before records are dropped
+pub const DEFAULT_QUEUE: usize = 4096;
+
+type Accept = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+/// A connected client with its optional filter
+struct Client {
+    tx: mpsc::Sender<String>,
+    filter: Arc<Mutex<Option<Filter>>>,
+    /// Records dropped since the last successful send
+    dropped: usize,
+}
+
+/// Sink that formats records and fans them out to all connected clients.
+/// Records are dropped for clients that cannot keep up.
+pub struct Server {
+    format: Format,
+    queue: usize,
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl Server {
+    pub fn new(format: Format, queue: usize) -> Server {
+        Server {
+            format,
+            queue,
+            clients: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Bind to `tcp://host:port` or `unix:///path` and return the future that
+    /// accepts clients
+    pub fn listen(&self, addr: &str) -> Result<Accept, Error> {
+        let url = Url::parse(addr).map_err(|e| format_err!("Invalid address {}: {}", addr, e))?;
+        let clients = self.clients.clone();
+        let format = self.format.clone();
+        let queue = self.queue;
+        match url.scheme() {
+            "tcp" => {
+                let addr = url
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| format_err!("Failed to resolve {}", addr))?;
+                let listener = TcpListener::bind(&addr)
+                    .map_err(|e| format_err!("Failed to bind {}: {}", addr, e))?;
+                let accept = listener
+                    .incoming()
+                    .for_each(move |stream| {
+                        let name = stream
+                            .peer_addr()
+                            .map(|a| a.to_string())
+                            .unwrap_or_else(|_| "unknown".into());
+                        accept(&clients, &format, queue, stream, name);
+                        Ok(())
+                    })
+                    .map_err(|e| eprintln!("Failed to accept client: {e}"));
+                Ok(Box::new(accept))
+            }
+            #[cfg(unix)]
+            "unix" => {
+                use std::{fs, os::unix::fs::FileTypeExt};
+                use tokio::net::UnixListener;
+
+                let path = url.path().to_owned();
+                // Remove a stale socket of a previous run
+                if let Ok(metadata) = fs::metadata(&path) {
+                    if metadata.file_type().is_socket() {
+                        fs::remove_file(&path)?;
+                    }
+                }
+                let listener = UnixListener::bind(&path)
+                    .map_err(|e| format_err!("Failed to bind {}: {}", path, e))?;
+                let accept = listener
+                    .incoming()
+                    .for_each(move |stream| {
+                        accept(&clients, &format, queue, stream, path.clone());
+                        Ok(())
+                    })
+                    .map_err(|e| eprintln!("Failed to accept client: {e}"));
+                Ok(Box::new(accept))
+            }
+            scheme => Err(format_err!("Unsupported address scheme {}", scheme)),
+        }
+    }
+
+    /// Queue `line` and report dropped records once the client catches up.
+    /// Returns false if the client disconnected.
+    fn deliver(&self, client: &mut Client, line: &str) -> bool {
+        if client.dropped > 0 {
+            let notice = format!("{} records dropped", client.dropped);
+            match self
+                .format
+                .fmt_record(&marker(&notice))
+                .map(|n| client.tx.try_send(n))
+            {
+                Ok(Ok(())) => client.dropped = 0,
+                Ok(Err(ref e)) if e.is_disconnected() => return false,
+                _ => {
+                    client.dropped += 1;
+                    return true;
+                }
+            }
+        }
+
+        match client.tx.try_send(line.to_owned()) {
+            Ok(()) => true,
+            Err(ref e) if e.is_full() => {
+                client.dropped += 1;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Register a new client and spawn the tasks that write the queued lines and
+/// read filter updates
+fn accept<S>(
+    clients: &Arc<Mutex<Vec<Client>>>,
+    format: &Format,
+    queue: usize,
+    stream: S,
+    name: String,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (read, write) = stream.split();
+    let (tx, rx) = mpsc::channel(queue);
+    let filter = Arc::new(Mutex::new(None));
+
+    eprintln!("Client {name} connected");
+    clients
+        .lock()
+        .expect("Failed to lock clients")
+        .push(Client {
+            tx: tx.clone(),
+            filter: filter.clone(),
+            dropped: 0,
+        });
+
+    let writer = rx
+        .map_err(|_| io::Error::other("Client queue closed"))
+        .forward(FramedWrite::new(write, LinesCodec::new()))
+        .map(|_| ())
+        .then(move |_| {
+            eprintln!("Client {name} disconnected");
+            Ok(())
+        });
+    tokio::spawn(writer);
+
+    // Every line received replaces the filter of the client. Invalid filters
+    // are reported in the format of the stream.
+    let format = format.clone();
+    let mut tx = tx;
+    let reader = FramedRead::new(read, LinesCodec::new())
+        .for_each(move |line| {
+            match filter::parse(&line) {
+                Ok(f) => *filter.lock().expect("Failed to lock filter") = f,
+                Err(e) => {
+                    let notice = marker(&format!("Invalid filter \"{line}\": {e}"));
+                    if let Ok(notice) = format.fmt_record(&notice) {
+                        tx.try_send(notice).ok();
+                    }
+                }
+            }
+            Ok(())
+        })
+        .map_err(|_| ());
+    tokio::spawn(reader);
+}
+
+impl Sink for Server {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let mut clients = self.clients.lock().expect("Failed to lock clients");
+        if clients.is_empty() {
+            return Ok(AsyncSink::Ready);
+        }
+
+        let line = self.format.fmt_record(&record)?;
+        clients.retain_mut(|client| {
+            let pass = client
+                .filter
+                .lock()
+                .expect("Failed to lock filter")
+                .as_ref()
+                .map(|f| f.filter(&record))
+                .unwrap_or(true);
+            if !pass {
+                return true;
+            }
+            self.deliver(client, &line)
+        });
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+fn client(server: &Server, filter: Option<&str>) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel(server.queue);
+    let filter = filter.map(|f| filter::parse(f).unwrap().unwrap());
+    server.clients.lock().unwrap().push(Client {
+        tx,
+        filter: Arc::new(Mutex::new(filter)),
+        dropped: 0,
+    });
+    rx
+}
+
+#[cfg(test)]
+fn record(message: &str) -> Record {
+    Record {
+        message: message.to_owned(),
+        raw: message.to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_fan_out() {
+    let mut server = Server::new(Format::Raw, DEFAULT_QUEUE);
+    let all = client(&server, None);
+    let other = client(&server, None);
+    let filtered = client(&server, Some("-m ^b$"));
+
+    for message in &["a", "b", "c"] {
+        server.start_send(record(message)).unwrap();
+    }
+    drop(server);
+
+    let lines = |rx: mpsc::Receiver<String>| rx.wait().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(lines(all), vec!["a", "b", "c"]);
+    assert_eq!(lines(other), vec!["a", "b", "c"]);
+    assert_eq!(lines(filtered), vec!["b"]);
+}
+
+#[test]
+fn test_slow_client() {
+    let mut server = Server::new(Format::Raw, 1);
+    // The channel holds queue + 1 lines for the single sender
+    let mut rx = client(&server, None).wait();
+    for message in &["a", "b", "c", "d"] {
+        server.start_send(record(message)).unwrap();
+    }
+    assert_eq!(rx.next(), Some(Ok("a".to_owned())));
+    assert_eq!(rx.next(), Some(Ok("b".to_owned())));
+
+    // The notice is queued once the client caught up
+    server.start_send(record("e")).unwrap();
+    assert_eq!(rx.next(), Some(Ok("--- 2 records dropped ---".to_owned())));
+    assert_eq!(rx.next(), Some(Ok("e".to_owned())));
+
+    // Disconnected clients are removed
+    drop(rx);
+    server.start_send(record("f")).unwrap();
+    assert!(server.clients.lock().unwrap().is_empty());
+}
@@ -0,0 +1,305 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{LogStream, StreamData};
+use failure::{err_msg, format_err, Error};
+use futures::{sync::mpsc, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use rogcat::record::{Level, Output, Record, Timestamp};
+use rusqlite::{params, Connection, OpenFlags, Row};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    thread,
+};
+use time::{strftime, strptime};
+
+/// Number of records written in one transaction
+const BATCH: usize = 1000;
+/// Header of sqlite database files
+const MAGIC: &[u8] = b"SQLite format 3\0";
+/// Format of the timestamp column. ISO 8601 in order to sort across years.
+const TIMESTAMP: &str = "%Y-%m-%d %H:%M:%S.%f";
+
+const SCHEMA: &str = "
+PRAGMA synchronous = NORMAL;
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT,
+    level TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    process TEXT NOT NULL,
+    thread TEXT NOT NULL,
+    message TEXT NOT NULL,
+    raw TEXT NOT NULL,
+    location TEXT,
+    output TEXT
+);
+CREATE INDEX IF NOT EXISTS records_timestamp ON records (timestamp);
+CREATE INDEX IF NOT EXISTS records_tag ON records (tag);
+CREATE INDEX IF NOT EXISTS records_process ON records (process);
+CREATE INDEX IF NOT EXISTS records_level ON records (level);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages USING fts5 (message, content = 'records', content_rowid = 'id');
+";
+
+/// Returns true if `path` is a sqlite database
+pub fn is_database(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map(|_| header == MAGIC)
+        .unwrap_or(false)
+}
+
+/// Sink that inserts records into a sqlite database in batched transactions
+pub struct Database {
+    connection: Connection,
+    batch: Vec<Record>,
+}
+
+impl Database {
+    /// Create the database at `path` or fail if it exists and `overwrite` is not set
+    pub fn create(path: &Path, overwrite: bool) -> Result<Database, Error> {
+        if path.exists() {
+            if !overwrite {
+                return Err(format_err!(
+                    "{} exists. Use overwrite flag to force!",
+                    path.display()
+                ));
+            }
+            fs::remove_file(path)
+                .map_err(|e| format_err!("Failed to remove {}: {}", path.display(), e))?;
+        }
+        let connection = Connection::open(path)
+            .map_err(|e| format_err!("Failed to open {}: {}", path.display(), e))?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Database {
+            connection,
+            batch: Vec::with_capacity(BATCH),
+        })
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO records (timestamp, level, tag, process, thread, message, raw, location, output)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            let mut index = transaction
+                .prepare_cached("INSERT INTO messages (rowid, message) VALUES (?1, ?2)")?;
+            for record in self.batch.drain(..) {
+                let timestamp = match record.timestamp {
+                    Some(ref timestamp) => Some(strftime(TIMESTAMP, timestamp)?),
+                    None => None,
+                };
+                let id = insert.insert(params![
+                    timestamp,
+                    record.level.to_string(),
+                    record.tags.join(","),
+                    record.process,
+                    record.thread,
+                    record.message,
+                    record.raw,
+                    record.location,
+                    record.output.map(|o| o.to_string()),
+                ])?;
+                index.execute(params![id, record.message])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+impl Sink for Database {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        self.batch.push(record);
+        if self.batch.len() >= BATCH {
+            self.flush()?;
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.flush()?;
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Failed to write database: {e}");
+        }
+    }
+}
+
+fn record(row: &Row) -> rusqlite::Result<Record> {
+    let timestamp = row
+        .get::<_, Option<String>>(0)?
+        .and_then(|t| strptime(&t, TIMESTAMP).ok())
+        .map(Timestamp::new);
+    let tag = row.get::<_, String>(2)?;
+    let output = row
+        .get::<_, Option<String>>(8)?
+        .and_then(|o| match o.as_str() {
+            "stdout" => Some(Output::Stdout),
+            "stderr" => Some(Output::Stderr),
+            _ => None,
+        });
+    Ok(Record {
+        timestamp,
+        level: Level::from(row.get::<_, String>(1)?.as_str()),
+        tags: if tag.is_empty() {
+            Vec::new()
+        } else {
+            tag.split(',').map(ToOwned::to_owned).collect()
+        },
+        process: row.get(3)?,
+        thread: row.get(4)?,
+        message: row.get(5)?,
+        raw: row.get(6)?,
+        location: row.get(7)?,
+        output,
+        elapsed: None,
+        gap: false,
+    })
+}
+
+/// Select records from the database at `path` in the order they were written.
+/// `fts` is a full text query on the messages and `condition` a sql expression
+/// on the columns of the records table.
+fn query<F>(path: &Path, fts: Option<&str>, condition: Option<&str>, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(Record) -> bool,
+{
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format_err!("Failed to open {}: {}", path.display(), e))?;
+
+    let mut conditions = Vec::new();
+    if fts.is_some() {
+        conditions.push("id IN (SELECT rowid FROM messages WHERE messages MATCH ?1)".to_owned());
+    }
+    if let Some(condition) = condition {
+        conditions.push(format!("({condition})"));
+    }
+    let mut sql = "SELECT timestamp, level, tag, process, thread, message, raw, location, output FROM records".to_owned();
+    if !conditions.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+    }
+    sql.push_str(" ORDER BY id");
+
+    let mut statement = connection
+        .prepare(&sql)
+        .map_err(|e| format_err!("Invalid query: {}", e))?;
+    let mut rows = match fts {
+        Some(fts) => statement.query(params![fts])?,
+        None => statement.query([])?,
+    };
+    while let Some(row) = rows.next()? {
+        if !f(record(row)?) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Stream the records of the database at `path`. See `query`.
+pub fn records(path: PathBuf, fts: Option<String>, condition: Option<String>) -> LogStream {
+    let (tx, rx) = mpsc::channel(BATCH);
+    thread::spawn(move || {
+        let mut tx = Some(tx);
+        // Stop once the receiver is gone
+        let mut send = |item| match tx.take().map(|tx| tx.send(item).wait().ok()) {
+            Some(Some(t)) => {
+                tx = Some(t);
+                true
+            }
+            _ => false,
+        };
+        let result = query(&path, fts.as_deref(), condition.as_deref(), |record| {
+            send(Ok(StreamData::Record(record)))
+        });
+        if let Err(e) = result {
+            send(Err(e));
+        }
+    });
+
+    Box::new(
+        rx.map_err(|_| err_msg("Failed to read database"))
+            .and_then(|item| item),
+    )
+}
+
+#[test]
+fn test_database() {
+    let dir = crate::utils::TempDir::new("sqlite");
+    let path = dir.path().join("test.db");
+
+    let records = (0..3)
+        .map(|i| Record {
+            timestamp: Some(Timestamp::new(
+                strptime("2019-01-02 03:04:05.678", TIMESTAMP).unwrap(),
+            )),
+            message: format!("message {i}"),
+            level: Level::Warn,
+            tags: vec!["A".into(), "B".into()],
+            process: "1".into(),
+            raw: format!("raw {i}"),
+            output: Some(Output::Stderr),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    {
+        let mut database = Database::create(&path, false).unwrap();
+        for record in &records {
+            database.start_send(record.clone()).unwrap();
+        }
+    }
+    assert!(is_database(&path));
+    assert!(Database::create(&path, false).is_err());
+
+    let select = |fts: Option<&str>, condition: Option<&str>| {
+        let mut selected = Vec::new();
+        query(&path, fts, condition, |r| {
+            selected.push(r);
+            true
+        })
+        .unwrap();
+        selected
+    };
+    assert_eq!(select(None, None), records);
+    assert_eq!(select(None, Some("timestamp LIKE '2019-01-02 %'")), records);
+    assert_eq!(
+        select(Some("message AND 1"), None),
+        vec![records[1].clone()]
+    );
+    assert_eq!(select(None, Some("raw != 'raw 1'")).len(), 2);
+    assert!(query(&path, None, Some("foo ="), |_| true).is_err());
+}
@@ -0,0 +1,277 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::{Level, Record};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Default number of rows in the top lists
+pub const DEFAULT_TOP: usize = 10;
+/// Number of buckets of the rate sparkline
+const BUCKETS: usize = 60;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+lazy_static! {
+    static ref NUMBER: Regex = Regex::new(r"(0x[0-9a-fA-F]+|\d+)").unwrap();
+}
+
+/// Replace numbers in `message` to group messages that differ in ids only
+pub fn normalize(message: &str) -> String {
+    NUMBER.replace_all(message, "#").into_owned()
+}
+
+/// Counters collected from records
+#[derive(Debug, Default)]
+pub struct Stats {
+    records: usize,
+    unparsed: usize,
+    tags: HashMap<String, usize>,
+    processes: HashMap<String, usize>,
+    threads: HashMap<String, usize>,
+    levels: Vec<(Level, usize)>,
+    messages: HashMap<String, usize>,
+    /// Records per second since the epoch of the record timestamps
+    seconds: BTreeMap<i64, usize>,
+    /// Highest number of records within a second and its timestamp
+    peak: Option<(usize, String)>,
+}
+
+impl Stats {
+    /// Count a line of the input that no parser matched. Called before
+    /// filtering since the filters rarely match unparsed lines.
+    pub fn add_unparsed(&mut self) {
+        self.unparsed += 1;
+    }
+
+    /// Count `record`
+    pub fn add(&mut self, record: &Record) {
+        self.records += 1;
+        for tag in &record.tags {
+            *self.tags.entry(tag.clone()).or_default() += 1;
+        }
+        if !record.process.is_empty() {
+            *self.processes.entry(record.process.clone()).or_default() += 1;
+        }
+        if !record.thread.is_empty() {
+            *self.threads.entry(record.thread.clone()).or_default() += 1;
+        }
+        match self.levels.iter_mut().find(|(l, _)| *l == record.level) {
+            Some((_, count)) => *count += 1,
+            None => self.levels.push((record.level.clone(), 1)),
+        }
+        *self.messages.entry(normalize(&record.message)).or_default() += 1;
+        if let Some(ref timestamp) = record.timestamp {
+            let count = self.seconds.entry(timestamp.to_timespec().sec).or_default();
+            *count += 1;
+            if self.peak.as_ref().map(|(p, _)| *count > *p).unwrap_or(true) {
+                let at = time::strftime("%m-%d %H:%M:%S", timestamp).unwrap_or_default();
+                self.peak = Some((*count, at));
+            }
+        }
+    }
+
+    /// Summarize the counters with `top` entries per list
+    pub fn summary(&self, top: usize) -> Summary {
+        fn top_n(counts: &HashMap<String, usize>, n: usize) -> Vec<Count> {
+            let mut rows = counts
+                .iter()
+                .map(|(name, count)| Count {
+                    name: name.clone(),
+                    count: *count,
+                })
+                .collect::<Vec<_>>();
+            rows.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+            rows.truncate(n);
+            rows
+        }
+
+        let mut levels = self.levels.clone();
+        levels.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("Levels are ordered"));
+
+        let (first, last) = match (self.seconds.keys().next(), self.seconds.keys().last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => (0, 0),
+        };
+        let span = (last - first + 1) as usize;
+        let bucket_seconds = span.div_ceil(BUCKETS).max(1);
+        let mut rate = vec![0usize; span.div_ceil(bucket_seconds)];
+        if !self.seconds.is_empty() {
+            for (second, count) in &self.seconds {
+                rate[(second - first) as usize / bucket_seconds] += count;
+            }
+        }
+
+        Summary {
+            records: self.records,
+            unparsed: self.unparsed,
+            levels: levels
+                .iter()
+                .map(|(level, count)| Count {
+                    name: level.to_string(),
+                    count: *count,
+                })
+                .collect(),
+            tags: top_n(&self.tags, top),
+            processes: top_n(&self.processes, top),
+            threads: top_n(&self.threads, top),
+            messages: top_n(&self.messages, top),
+            duration: if self.seconds.is_empty() {
+                0
+            } else {
+                last - first
+            },
+            bucket_seconds,
+            rate,
+            peak_per_second: self.peak.as_ref().map(|(c, _)| *c).unwrap_or(0),
+            peak_at: self.peak.as_ref().map(|(_, at)| at.clone()),
+        }
+    }
+}
+
+/// Number of records of a tag, process...
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Count {
+    pub name: String,
+    pub count: usize,
+}
+
+/// Result of the statistics
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub records: usize,
+    pub unparsed: usize,
+    pub levels: Vec<Count>,
+    pub tags: Vec<Count>,
+    pub processes: Vec<Count>,
+    pub threads: Vec<Count>,
+    pub messages: Vec<Count>,
+    /// Seconds between the first and last timestamp
+    pub duration: i64,
+    /// Seconds per rate bucket
+    pub bucket_seconds: usize,
+    /// Records per bucket
+    pub rate: Vec<usize>,
+    pub peak_per_second: usize,
+    pub peak_at: Option<String>,
+}
+
+impl Summary {
+    pub fn print(&self) {
+        fn table(title: &str, rows: &[Count]) {
+            if rows.is_empty() {
+                return;
+            }
+            let width = rows
+                .iter()
+                .map(|c| c.name.chars().count())
+                .chain(Some(title.len()))
+                .max()
+                .unwrap_or(0)
+                .min(80);
+            println!();
+            println!("{title:width$}  Count");
+            for Count { name, count } in rows {
+                let name = name.chars().take(width).collect::<String>();
+                println!("{name:width$}  {count:>5}");
+            }
+        }
+
+        println!(
+            "{} record(s), {} unparsed, {}s",
+            self.records, self.unparsed, self.duration
+        );
+
+        if !self.levels.is_empty() {
+            let max = self.levels.iter().map(|c| c.count).max().unwrap_or(1);
+            println!();
+            println!("Level  Count");
+            for Count { name: level, count } in &self.levels {
+                let bar = "#".repeat((count * 40).div_ceil(max));
+                println!("{level:5}  {count:>5} {bar}");
+            }
+        }
+
+        table("Tag", &self.tags);
+        table("Process", &self.processes);
+        table("Thread", &self.threads);
+        table("Message", &self.messages);
+
+        if self.rate.len() > 1 {
+            println!();
+            println!(
+                "Records per {}s: {}",
+                self.bucket_seconds,
+                sparkline(&self.rate)
+            );
+            if let Some(ref at) = self.peak_at {
+                println!("Peak: {}/s at {}", self.peak_per_second, at);
+            }
+        }
+    }
+}
+
+/// Render `values` as a line of block characters
+fn sparkline(values: &[usize]) -> String {
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|v| SPARKS[v * (SPARKS.len() - 1) / max])
+        .collect()
+}
+
+#[test]
+fn test_stats() {
+    let record = |tag: &str, level, message: &str, second| Record {
+        timestamp: Some(rogcat::record::Timestamp::new(time::at_utc(
+            time::Timespec::new(second, 0),
+        ))),
+        tags: vec![tag.to_owned()],
+        level,
+        message: message.to_owned(),
+        process: "1".into(),
+        ..Default::default()
+    };
+    let mut stats = Stats::default();
+    stats.add(&record("A", Level::Info, "pid 123 died", 0));
+    stats.add(&record("A", Level::Warn, "pid 456 died", 0));
+    stats.add(&record("B", Level::Info, "foo", 2));
+    stats.add(&Record::default());
+    stats.add_unparsed();
+
+    let summary = stats.summary(1);
+    assert_eq!(summary.records, 4);
+    assert_eq!(summary.unparsed, 1);
+    let count = |name: &str, count| Count {
+        name: name.to_owned(),
+        count,
+    };
+    assert_eq!(summary.tags, vec![count("A", 2)]);
+    assert_eq!(summary.messages, vec![count("pid # died", 2)]);
+    assert_eq!(
+        summary.levels,
+        vec![count("-", 1), count("I", 2), count("W", 1)]
+    );
+    assert_eq!(summary.duration, 2);
+    assert_eq!(summary.rate, vec![2, 0, 1]);
+    assert_eq!(summary.peak_per_second, 2);
+    assert_eq!(sparkline(&summary.rate), "█▁▄");
+}
@@ -19,37 +19,59 @@
 // SOFTWARE.
 
 use crate::{
+    adb,
+    bugreport::{Bugreport, Section},
     cli::cli,
-    reader::stdin,
-    utils::{self, adb},
-    StreamData, DEFAULT_BUFFER,
+    convert::{self, Converter},
+    crashes::{self, Reports},
+    diff::{self, Capture},
+    filter, highlight, profiles, reader,
+    replay::{self, Replay},
+    server::{self, Server},
+    stats::{self, Stats},
+    terminal, utils, RecordStream, StreamData, DEFAULT_BUFFER,
 };
 use clap::{crate_name, value_t, ArgMatches};
-use failure::{err_msg, Error};
+use failure::{err_msg, format_err, Error};
 use futures::{
-    future::ok, stream::Stream, sync::oneshot, Async, AsyncSink, Future, Poll, Sink, StartSend,
+    stream::{iter_ok, Stream},
+    sync::oneshot,
+    Future,
 };
 use indicatif::{ProgressBar, ProgressStyle};
-use rogcat::record::Level;
+use rogcat::{
+    parser::Parser,
+    record::{Format, Level, Record},
+};
 use std::{
     borrow::ToOwned,
     fs::{DirBuilder, File},
-    io::{BufReader, Write},
+    io::{self, BufRead, BufReader, BufWriter, Write},
     path::{Path, PathBuf},
     process::{exit, Command, Stdio},
+    sync::{Arc, Mutex},
 };
 use time::{now, strftime};
 use tokio::{io::lines, runtime::Runtime};
 use tokio_process::CommandExt;
-use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// First SDK version (Android 7) that generates zipped bugreports
+const SDK_BUGREPORTZ: u32 = 24;
 
-pub fn run(args: &ArgMatches) {
+pub fn run(args: &ArgMatches<'static>) {
     match args.subcommand() {
-        ("bugreport", Some(sub_matches)) => bugreport(sub_matches),
+        ("bugreport", Some(sub_matches)) => bugreport(args, sub_matches),
         ("clear", Some(sub_matches)) => clear(sub_matches),
+        ("convert", Some(sub_matches)) => convert(sub_matches),
+        ("diff", Some(sub_matches)) => diff(sub_matches),
+        ("crashes", Some(sub_matches)) => crashes(sub_matches),
+        ("serve", Some(sub_matches)) => serve(sub_matches),
+        ("stats", Some(sub_matches)) => stats(sub_matches),
         ("completions", Some(sub_matches)) => completions(sub_matches),
-        ("devices", _) => devices(),
+        ("devices", Some(sub_matches)) => devices(sub_matches),
         ("log", Some(sub_matches)) => log(sub_matches),
+        ("profiles", Some(sub_matches)) => profiles(sub_matches),
         (_, _) => (),
     }
 }
@@ -110,48 +132,185 @@ impl Drop for ZipFile {
     }
 }
 
-fn report_filename() -> Result<String, Error> {
+fn report_filename(extension: &str) -> Result<String, Error> {
     #[cfg(not(windows))]
     let sep = ":";
     #[cfg(windows)]
     let sep = "_";
 
     let format = format!("%m-%d_%H{sep}%M{sep}%S");
-    Ok(format!("{}-bugreport.txt", strftime(&format, &now())?))
+    Ok(format!(
+        "{}-bugreport.{}",
+        strftime(&format, &now())?,
+        extension
+    ))
 }
 
-/// Performs a dumpstate and write to fs. Note: The Android 7+ dumpstate is not supported.
-pub fn bugreport(args: &ArgMatches) {
-    let filename = value_t!(args.value_of("file"), String)
-        .unwrap_or_else(|_| report_filename().expect("Failed to generate filename"));
+/// Capture a bugreport. Devices running Android 7+ generate a zip with
+/// `adb bugreport PATH`. Older devices stream a dumpstate that is written to fs.
+pub fn bugreport(args: &ArgMatches, sub_args: &ArgMatches<'static>) {
+    if let ("inspect", Some(sub_matches)) = sub_args.subcommand() {
+        bugreport_inspect(sub_matches);
+    }
+
+    let serial = sub_args.value_of("dev").or_else(|| args.value_of("dev"));
+    let sdk = utils::sdk_version(serial).unwrap_or_else(|e| {
+        eprintln!("Failed to detect Android version: {e}");
+        exit(1)
+    });
+
+    let zipped = sdk >= SDK_BUGREPORTZ;
+    if zipped && sub_args.is_present("zip") {
+        eprintln!("Ignoring --zip: Android 7+ always creates zipped bugreports");
+    }
+    let mut filename = value_t!(sub_args.value_of("file"), String).unwrap_or_else(|_| {
+        report_filename(if zipped { "zip" } else { "txt" }).expect("Failed to generate filename")
+    });
+    // adb bugreport appends .zip if missing
+    if zipped && !filename.ends_with(".zip") {
+        filename.push_str(".zip");
+    }
     let filename_path = PathBuf::from(&filename);
-    if !args.is_present("overwrite") && filename_path.exists() {
+    if !sub_args.is_present("overwrite") && filename_path.exists() {
         eprintln!("File {filename} already exists");
         exit(1);
     }
-    let mut adb = adb().expect("Failed to find adb");
 
-    if args.is_present("dev") {
-        let device = value_t!(args, "dev", String).unwrap_or_else(|e| e.exit());
-        adb.push::<String>("-s".into());
-        adb.push(device);
+    let dir = filename_path.parent().unwrap_or_else(|| Path::new(""));
+    if !dir.is_dir() {
+        DirBuilder::new()
+            .recursive(true)
+            .create(dir)
+            .expect("Failed to create outfile parent directory");
     }
 
-    let mut child = Command::new(adb)
-        .arg("bugreport")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn_async()
-        .expect("Failed to launch adb");
-    let stdout = BufReader::new(child.stdout().take().unwrap());
+    let result = if zipped {
+        bugreport_zip(serial, &filename_path)
+    } else {
+        bugreport_legacy(serial, sub_args.is_present("zip"), &filename)
+    };
 
-    let dir = filename_path.parent().unwrap_or_else(|| Path::new(""));
+    if let Err(e) = result {
+        eprintln!("Failed to create bugreport: {e}");
+        exit(1);
+    }
+
+    if zipped && sub_args.is_present("index") {
+        if let Err(e) = bugreport_index(&filename_path) {
+            eprintln!("Failed to index {}: {}", filename_path.display(), e);
+            exit(1);
+        }
+    }
+
+    exit(0);
+}
+
+/// Capture a bugreport into `dir` with a generated filename
+pub fn capture_bugreport(serial: Option<&str>, dir: &Path) -> Result<PathBuf, Error> {
+    let sdk = utils::sdk_version(serial)?;
+    let zipped = sdk >= SDK_BUGREPORTZ;
+    let filename = dir.join(report_filename(if zipped { "zip" } else { "txt" })?);
     if !dir.is_dir() {
         DirBuilder::new()
             .recursive(true)
             .create(dir)
-            .expect("Failed to create outfile parent directory");
+            .map_err(|e| format_err!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    if zipped {
+        bugreport_zip(serial, &filename)?;
+    } else {
+        let name = filename
+            .to_str()
+            .ok_or_else(|| err_msg("Invalid bugreport filename"))?;
+        bugreport_legacy(serial, false, name)?;
+    }
+    Ok(filename)
+}
+
+/// Line of the output of `adb bugreport PATH`
+#[derive(Debug, PartialEq)]
+enum BugreportStatus {
+    /// `[ 45%] generating bugreport-...zip` with the percentage of `bugreportz -p`
+    Progress(u64),
+    /// `Bug report copied to PATH`
+    Done,
+    /// `Device failed to take a zipped bugreport: ...` or a failed copy
+    Failed(String),
+}
+
+impl BugreportStatus {
+    fn parse(line: &str) -> Option<BugreportStatus> {
+        let line = line.trim();
+        if let Some((percent, _)) = line.strip_prefix('[').and_then(|l| l.split_once("%]")) {
+            percent.trim().parse().ok().map(BugreportStatus::Progress)
+        } else if line.starts_with("Bug report copied to") {
+            Some(BugreportStatus::Done)
+        } else if line.starts_with("Device failed") || line.contains("could not be copied") {
+            Some(BugreportStatus::Failed(line.to_owned()))
+        } else {
+            None
+        }
     }
+}
+
+/// Generate a zip with `adb bugreport PATH`. Adb runs `bugreportz -p` on the
+/// device, reports the progress, pulls the zip and removes it from the device.
+fn bugreport_zip(serial: Option<&str>, filename: &Path) -> Result<(), Error> {
+    let mut child = utils::adb_command(serial)?
+        .arg("bugreport")
+        .arg(filename)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| err_msg("Failed to read adb bugreport"))?;
+
+    let progress = ProgressBar::new(100);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.yellow} {msg:.dim.bold} {percent:>3.dim}% {elapsed_precise:.dim} [{bar:40.yellow/green}]")
+            .progress_chars("•• "),
+    );
+    progress.set_message("Generating bugreport");
+
+    // Adb updates the progress in place with carriage returns
+    let mut failure = None;
+    for line in BufReader::new(stdout).split(b'\r') {
+        let line = line?;
+        for line in String::from_utf8_lossy(&line).lines() {
+            match BugreportStatus::parse(line) {
+                Some(BugreportStatus::Progress(percent)) => progress.set_position(percent),
+                Some(BugreportStatus::Done) => progress.set_position(100),
+                Some(BugreportStatus::Failed(msg)) => failure = Some(msg),
+                None => (),
+            }
+        }
+    }
+
+    let status = child.wait()?;
+    if let Some(msg) = failure {
+        progress.finish_and_clear();
+        return Err(err_msg(msg));
+    }
+    if !status.success() {
+        progress.finish_and_clear();
+        return Err(format_err!("adb bugreport failed with {}", status));
+    }
+
+    progress.set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
+    progress.finish_with_message(&format!("Finished {}.", filename.display()));
+    Ok(())
+}
+
+/// Performs a dumpstate and write to fs. Used for devices prior to Android 7
+fn bugreport_legacy(serial: Option<&str>, zip: bool, filename: &str) -> Result<(), Error> {
+    let mut child = utils::adb_command(serial)?
+        .arg("bugreport")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn_async()?;
+    let stdout = BufReader::new(child.stdout().take().unwrap());
 
     let progress = ProgressBar::new(u64::MAX);
     progress.set_style(
@@ -161,10 +320,10 @@ pub fn bugreport(args: &ArgMatches) {
     );
     progress.set_message("Connecting");
 
-    let mut write = if args.is_present("zip") {
-        Box::new(ZipFile::create(&filename).expect("Failed to create zip file")) as Box<dyn Write>
+    let mut write = if zip {
+        Box::new(ZipFile::create(filename)?) as Box<dyn Write>
     } else {
-        Box::new(File::create(&filename).expect("Failed to craete file")) as Box<dyn Write>
+        Box::new(File::create(filename)?) as Box<dyn Write>
     };
 
     progress.set_message("Pulling bugreport line");
@@ -172,27 +331,159 @@ pub fn bugreport(args: &ArgMatches) {
     // TODO: Migrate to tokio::fs::File
     let output = tokio::io::lines(stdout)
         .for_each(|l| {
-            write.write_all(l.as_bytes()).expect("Failed to write");
-            write.write_all(b"\n").expect("Failed to write");
+            write.write_all(l.as_bytes())?;
+            write.write_all(b"\n")?;
             progress.inc(1);
-            ok(())
+            Ok(())
         })
         .then(|r| {
             progress.set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
-            progress.finish_with_message(&format!("Finished {}.", filename_path.display()));
+            progress.finish_with_message(&format!("Finished {filename}."));
             r
-        })
-        .map_err(|e| {
-            eprintln!("Failed to create bugreport: {e}");
-            exit(1);
         });
 
-    tokio::runtime::current_thread::block_on_all(output).expect("Runtime error");
+    tokio::runtime::current_thread::block_on_all(output).map_err(Into::into)
+}
+
+/// Print the entries of a bugreport zip and the sections of the main report
+fn bugreport_index(filename: &Path) -> Result<(), Error> {
+    let mut zip = ZipArchive::new(File::open(filename)?)?;
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i)?;
+        println!("{:>10} {}", entry.size(), entry.name());
+    }
+    println!();
+    print_sections(&Bugreport::open(filename)?);
+    Ok(())
+}
+
+fn print_sections(report: &Bugreport) {
+    for Section {
+        name,
+        command,
+        lines,
+    } in &report.sections
+    {
+        println!("{:>8} {} ({})", lines.len(), name, command);
+    }
+}
+
+/// List, print, extract or parse the sections of a bugreport
+pub fn bugreport_inspect(args: &ArgMatches<'static>) {
+    fn run(args: &ArgMatches<'static>) -> Result<(), Error> {
+        let file = args
+            .value_of("file")
+            .ok_or_else(|| err_msg("Missing bugreport file"))?;
+        let report = Bugreport::open(Path::new(file))?;
+
+        let selected = match args.values_of("section") {
+            Some(names) => {
+                let mut selected = Vec::new();
+                for name in names {
+                    let len = selected.len();
+                    selected.extend(report.sections(name));
+                    if selected.len() == len {
+                        return Err(format_err!("Unknown section {}", name));
+                    }
+                }
+                Some(selected)
+            }
+            None => None,
+        };
+
+        if args.is_present("properties") {
+            for (key, value) in report.properties() {
+                println!("{key}={value}");
+            }
+        } else if let Some(dir) = args.value_of("extract") {
+            let dir = Path::new(dir);
+            DirBuilder::new()
+                .recursive(true)
+                .create(dir)
+                .map_err(|e| format_err!("Failed to create {}: {}", dir.display(), e))?;
+            let sections = selected.unwrap_or_else(|| report.sections.iter().collect());
+            for (index, section) in sections.iter().enumerate() {
+                let name = section
+                    .name
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                    .collect::<String>();
+                let filename = dir.join(format!("{index:03}-{name}.txt"));
+                let mut file = File::create(&filename)
+                    .map_err(|e| format_err!("Failed to create {}: {}", filename.display(), e))?;
+                for line in &section.lines {
+                    writeln!(file, "{line}")?;
+                }
+                println!("{}", filename.display());
+            }
+        } else if args.is_present("logcat") {
+            let profile = profiles::from_args(args)?;
+            let args = &profiles::apply_subcommand(&["bugreport", "inspect"], args, &profile)?;
+            let filter = filter::from_args_profile(args, &profile)?;
+            let highlight = Arc::new(Mutex::new(highlight::from_args_profile(args, &profile)?));
+            let sink = terminal::try_from(args, highlight)?;
+            let sections = selected
+                .unwrap_or_else(|| report.sections.iter().filter(|s| s.is_logcat()).collect());
+            let mut parser = Parser::default();
+            let records = sections
+                .into_iter()
+                .flat_map(|s| s.lines.iter())
+                .filter(|l| !l.is_empty() && !l.starts_with("--------- beginning of"))
+                .map(|l| parser.parse(l.clone()))
+                .filter(|r| filter.filter(r));
+            let output = iter_ok::<_, Error>(records).forward(sink).map(|_| ());
+            tokio::runtime::current_thread::block_on_all(output)?;
+        } else if let Some(sections) = selected {
+            for line in sections.iter().flat_map(|s| s.lines.iter()) {
+                println!("{line}");
+            }
+        } else {
+            print_sections(&report);
+        }
+        Ok(())
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        exit(1);
+    }
     exit(0);
 }
 
-pub fn devices() {
-    let mut child = Command::new(adb().expect("Failed to find adb"))
+/// List devices via the adb server and fall back to adb devices
+pub fn devices(args: &ArgMatches) {
+    let client = adb::Client::default();
+    if args.is_present("track") {
+        match client.track_devices() {
+            Ok(track) => {
+                for devices in track {
+                    match devices {
+                        Ok(devices) => {
+                            println!("--- {} device(s)", devices.len());
+                            print_devices(&devices);
+                        }
+                        Err(e) => {
+                            eprintln!("Lost connection to adb server: {e}");
+                            exit(1);
+                        }
+                    }
+                }
+                eprintln!("Lost connection to adb server");
+                exit(1);
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1);
+            }
+        }
+    }
+
+    if let Ok(devices) = client.devices() {
+        print_devices(&devices);
+        exit(0);
+    }
+
+    let mut child = Command::new(utils::adb().expect("Failed to find adb"))
         .arg("devices")
         .stdout(Stdio::piped())
         .spawn_async()
@@ -220,6 +511,15 @@ pub fn devices() {
     );
 }
 
+fn print_devices(devices: &[adb::Device]) {
+    for device in devices {
+        match device.model {
+            Some(ref model) => println!("{} {} {}", device.serial, device.state, model),
+            None => println!("{} {}", device.serial, device.state),
+        }
+    }
+}
+
 struct Logger {
     tag: String,
     level: Level,
@@ -237,29 +537,20 @@ impl Logger {
     }
 }
 
-impl Sink for Logger {
-    type SinkItem = String;
-    type SinkError = Error;
-
-    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        let child = Command::new(adb()?)
-            .arg("shell")
-            .arg("log")
-            .arg("-p")
-            .arg(Self::level(&self.level))
-            .arg("-t")
-            .arg(format!("\"{}\"", &self.tag))
-            .arg(&item)
-            .stdout(Stdio::piped())
-            .output_async()
-            .map(|_| ())
-            .map_err(|_| ());
-        tokio::spawn(child);
-        Ok(AsyncSink::Ready)
-    }
-
-    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
-        Ok(Async::Ready(()))
+impl Logger {
+    fn log(&self, message: &str) -> Result<(), Error> {
+        utils::shell(
+            None,
+            &[
+                "log",
+                "-p",
+                Self::level(&self.level),
+                "-t",
+                &self.tag,
+                message,
+            ],
+        )
+        .map(|_| ())
     }
 }
 
@@ -268,33 +559,23 @@ pub fn log(args: &ArgMatches) {
     let message = args.value_of("MESSAGE").unwrap_or("");
     let tag = args.value_of("tag").unwrap_or("Rogcat").to_owned();
     let level = Level::from(args.value_of("level").unwrap_or(""));
+    let logger = Logger { tag, level };
     match message {
         "-" => {
-            let sink = Logger { tag, level };
-            let stream = stdin()
-                .map(|d| match d {
-                    StreamData::Line(l) => l,
-                    _ => panic!("Received non line item during log"),
-                })
-                .forward(sink)
-                .map(|_| ())
-                .map_err(|_| ());
-            tokio::run(stream);
+            // Each line is a blocking round-trip to the device
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                if let Err(e) = line.map_err(Into::into).and_then(|l| logger.log(&l)) {
+                    eprintln!("Failed to log: {e}");
+                    exit(1);
+                }
+            }
         }
         _ => {
-            let child = Command::new(adb().expect("Failed to find adb"))
-                .arg("shell")
-                .arg("log")
-                .arg("-p")
-                .arg(Logger::level(&level))
-                .arg("-t")
-                .arg(&tag)
-                .arg(format!("\"{message}\""))
-                .stdout(Stdio::piped())
-                .output_async()
-                .map(|_| ())
-                .map_err(|_| ());
-            tokio::run(child)
+            if let Err(e) = logger.log(message) {
+                eprintln!("Failed to log: {e}");
+                exit(1);
+            }
         }
     }
 
@@ -309,7 +590,7 @@ pub fn clear(args: &ArgMatches) {
         .or_else(|| utils::config_get("buffer"))
         .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
         .join(" -b ");
-    let child = Command::new(adb().expect("Failed to find adb"))
+    let child = Command::new(utils::adb().expect("Failed to find adb"))
         .arg("logcat")
         .arg("-c")
         .arg("-b")
@@ -321,3 +602,302 @@ pub fn clear(args: &ArgMatches) {
     let h = oneshot::spawn(child, &runtime.executor());
     exit(h.wait().expect("Failed to run").code().unwrap_or(1));
 }
+
+/// Detect crashes in logcat or files and write reports
+pub fn crashes(args: &ArgMatches) {
+    let source = reader::source(args).unwrap_or_else(|e| {
+        eprintln!("Failed to open source: {e}");
+        exit(1)
+    });
+
+    let dir = PathBuf::from(args.value_of("output").unwrap_or("crashes"));
+    let context = value_t!(args, "context", usize).unwrap_or(crashes::DEFAULT_CONTEXT);
+    let reports = Arc::new(Mutex::new(Reports::new(dir, context)));
+
+    let mut parser = Parser::default();
+    let r = reports.clone();
+    let detect = source
+        .map(move |data| data.into_record(&mut parser))
+        .for_each(move |record| r.lock().expect("Failed to lock reports").process(&record))
+        .map_err(|e| eprintln!("{e}"));
+    let mut runtime = Runtime::new().expect("Failed to start runtime");
+    runtime.spawn(crashes::expire(reports.clone()));
+    runtime.block_on(detect.select(reader::interrupted())).ok();
+
+    let mut reports = reports.lock().expect("Failed to lock reports");
+    if let Err(e) = reports.finish() {
+        eprintln!("{e}");
+        exit(1);
+    }
+    reports.summary.print(&mut io::stdout()).ok();
+    exit(0);
+}
+
+/// Run the source and pipeline once and serve the records to clients
+pub fn serve(args: &ArgMatches<'static>) {
+    fn run(args: &ArgMatches<'static>) -> Result<(), Error> {
+        let profile = profiles::from_args(args)?;
+        let args = &profiles::apply_subcommand(&["serve"], args, &profile)?;
+        let source = reader::source(args)?;
+        let filter = filter::from_args_profile(args, &profile)?;
+        let format = value_t!(args, "format", Format)?;
+        let queue = value_t!(args, "queue", usize).unwrap_or(server::DEFAULT_QUEUE);
+
+        let server = Server::new(format, queue);
+        let listeners = args
+            .values_of("listen")
+            .unwrap_or_default()
+            .map(|addr| {
+                let listener = server.listen(addr)?;
+                eprintln!("Listening on {addr}");
+                Ok(listener)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut parser = Parser::default();
+        let mut records: RecordStream =
+            Box::new(source.map(move |data| data.into_record(&mut parser)));
+        if let Some(config) = replay::from_args(args)? {
+            records = Box::new(Replay::new(records, config));
+        }
+        let pipeline = records
+            .filter(move |r| filter.filter(r))
+            .forward(server)
+            .map(|_| ())
+            .map_err(|e| eprintln!("{e}"));
+        let mut runtime = Runtime::new()?;
+        for listener in listeners {
+            runtime.spawn(listener);
+        }
+        runtime
+            .block_on(pipeline.select(reader::interrupted()))
+            .ok();
+        Ok(())
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        exit(1);
+    }
+    exit(0);
+}
+
+/// Count records and print a summary at the end of the input or on ctrl-c
+pub fn stats(args: &ArgMatches<'static>) {
+    fn run(args: &ArgMatches<'static>) -> Result<(), Error> {
+        let profile = profiles::from_args(args)?;
+        let args = &profiles::apply_subcommand(&["stats"], args, &profile)?;
+        let source = reader::source(args)?;
+        let filter = filter::from_args_profile(args, &profile)?;
+        let top = value_t!(args, "top", usize).unwrap_or(stats::DEFAULT_TOP);
+
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let s = stats.clone();
+        let u = stats.clone();
+        let mut parser = Parser::default();
+        let count = source
+            .map(move |data| match data {
+                StreamData::Line(line) | StreamData::Output(_, line) => {
+                    parser.try_parse(line).unwrap_or_else(|raw| {
+                        u.lock().expect("Failed to lock stats").add_unparsed();
+                        Record {
+                            message: raw.clone(),
+                            raw,
+                            ..Default::default()
+                        }
+                    })
+                }
+                data => data.into_record(&mut parser),
+            })
+            .filter(move |r| filter.filter(r))
+            .for_each(move |record| {
+                s.lock().expect("Failed to lock stats").add(&record);
+                Ok(())
+            })
+            .map_err(|e| eprintln!("{e}"));
+        let mut runtime = Runtime::new()?;
+        runtime.block_on(count.select(reader::interrupted())).ok();
+
+        let summary = stats.lock().expect("Failed to lock stats").summary(top);
+        if args.is_present("json") {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        } else {
+            summary.print();
+        }
+        Ok(())
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        exit(1);
+    }
+    exit(0);
+}
+
+pub fn convert(args: &ArgMatches) {
+    fn run(args: &ArgMatches) -> Result<(), Error> {
+        let from = args
+            .value_of("from")
+            .map(|f| f.parse::<Format>().map_err(err_msg))
+            .transpose()?;
+        let to = args
+            .value_of("to")
+            .unwrap_or("raw")
+            .parse::<Format>()
+            .map_err(err_msg)?;
+
+        let out: Box<dyn Write> = match args.value_of("output") {
+            Some(output) => {
+                let output = Path::new(output);
+                if output.exists() && !args.is_present("overwrite") {
+                    return Err(format_err!(
+                        "{} exists. Use overwrite flag to force!",
+                        output.display()
+                    ));
+                }
+                let file = File::create(output).map_err(|e| {
+                    format_err!("Failed to create output file {}: {}", output.display(), e)
+                })?;
+                Box::new(BufWriter::new(file))
+            }
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+        let mut writer = convert::Writer::new(to, out)?;
+        let mut converter = Converter::new(from);
+
+        match args.values_of("input").map(|i| i.collect::<Vec<_>>()) {
+            Some(ref input) if input != &["-"] => {
+                let input = input.iter().map(PathBuf::from).collect::<Vec<_>>();
+                for file in convert::files(&input)? {
+                    let reader = File::open(&file)
+                        .map_err(|e| format_err!("Failed to open {}: {}", file.display(), e))?;
+                    converter.convert(
+                        &file.display().to_string(),
+                        BufReader::new(reader),
+                        &mut writer,
+                    )?;
+                }
+            }
+            _ => {
+                let stdin = io::stdin();
+                converter.convert("stdin", stdin.lock(), &mut writer)?;
+            }
+        }
+        writer.finish()?;
+
+        if converter.failures > 0 {
+            eprintln!(
+                "Converted {} records. {} line(s) failed to parse",
+                converter.records, converter.failures
+            );
+        }
+        Ok(())
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        exit(1);
+    }
+    exit(0);
+}
+
+pub fn diff(args: &ArgMatches<'static>) {
+    fn run(args: &ArgMatches<'static>) -> Result<(), Error> {
+        let profile = profiles::from_args(args)?;
+        let args = &profiles::apply_subcommand(&["diff"], args, &profile)?;
+        let filter = filter::from_args_profile(args, &profile)?;
+        let read = |arg: &str| -> Result<Capture, Error> {
+            let filename = args.value_of(arg).expect("Missing capture");
+            let file = File::open(filename)
+                .map_err(|e| format_err!("Failed to open {}: {}", filename, e))?;
+            Capture::read(BufReader::new(file), &filter)
+        };
+        let (a, b) = (read("a")?, read("b")?);
+
+        if args.is_present("side-by-side") {
+            let width = value_t!(args, "width", usize)
+                .ok()
+                .or_else(utils::terminal_width)
+                .unwrap_or(160);
+            for line in diff::side_by_side(&a, &b, width)? {
+                println!("{line}");
+            }
+        } else {
+            let ratio = value_t!(args, "ratio", f64).unwrap_or(diff::DEFAULT_RATIO);
+            let min_delta = value_t!(args, "min-delta", usize).unwrap_or(diff::DEFAULT_MIN_DELTA);
+            diff::compare(&a, &b, ratio, min_delta).print();
+        }
+        Ok(())
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        exit(1);
+    }
+    exit(0);
+}
+
+pub fn profiles(args: &ArgMatches) {
+    fn run(args: &ArgMatches) -> Result<(), Error> {
+        match args.subcommand() {
+            ("list", _) => profiles::list(args),
+            ("show", Some(sub_args)) => profiles::show(args, sub_args.value_of("name").unwrap()),
+            ("validate", _) => {
+                let problems = profiles::validate(args)?;
+                for problem in &problems {
+                    println!("{problem}");
+                }
+                if problems.is_empty() {
+                    Ok(())
+                } else {
+                    Err(format_err!("Found {} problem(s)", problems.len()))
+                }
+            }
+            ("save", Some(sub_args)) => {
+                let name = sub_args.value_of("name").unwrap();
+                let path = profiles::save(args, name, sub_args)?;
+                println!("Saved profile {} to {}", name, path.display());
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        exit(1);
+    }
+    exit(0);
+}
+
+#[test]
+fn test_bugreport_status() {
+    assert_eq!(
+        BugreportStatus::parse(
+            "[ 12%] generating bugreport-sargo-RQ3A.210805.001.A1-2021-08-20-10-20-15.zip"
+        ),
+        Some(BugreportStatus::Progress(12))
+    );
+    assert_eq!(
+        BugreportStatus::parse("[100%] generating bugreport.zip"),
+        Some(BugreportStatus::Progress(100))
+    );
+    assert_eq!(
+        BugreportStatus::parse("Bug report copied to /tmp/bugreport.zip"),
+        Some(BugreportStatus::Done)
+    );
+    assert_eq!(
+        BugreportStatus::parse(
+            "Device failed to take a zipped bugreport: Bugreport read terminated abnormally"
+        ),
+        Some(BugreportStatus::Failed(
+            "Device failed to take a zipped bugreport: Bugreport read terminated abnormally".into()
+        ))
+    );
+    assert!(matches!(
+        BugreportStatus::parse("Bug report finished but could not be copied to '/tmp/x.zip'."),
+        Some(BugreportStatus::Failed(_))
+    ));
+    assert_eq!(BugreportStatus::parse("[foo] bar"), None);
+    assert_eq!(BugreportStatus::parse(""), None);
+}
@@ -19,40 +19,108 @@
 // SOFTWARE.
 
 use crate::{
-    profiles::Profile,
+    highlight::{self, Highlight, Style},
+    pager,
+    theme::{self, Theme},
     utils::{self, config_get, terminal_width},
     LogSink,
 };
-use clap::{values_t, ArgMatches};
+use clap::ArgMatches;
 use failure::{err_msg, format_err, Error};
-use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use futures::{stream, Async, AsyncSink, Poll, Sink, StartSend, Stream};
 use itertools::intersperse;
-use regex::Regex;
-use rogcat::record::{Format, Level, Record};
+use rogcat::record::{Format, Record};
 use std::{
     cmp::{max, min},
+    collections::VecDeque,
     convert::Into,
     io::{stdout, BufWriter, Write},
+    ops::Range,
     str::FromStr,
 };
-use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use termcolor::{Buffer, BufferWriter, ColorChoice, ColorSpec, WriteColor};
+use unicode_width::UnicodeWidthChar;
+
+/// Messages are wrapped to at least this many columns on narrow terminals
+const MIN_PAYLOAD_WIDTH: usize = 16;
+/// Number of recent records the column widths adapt to
+const COLUMN_WINDOW: usize = 128;
+/// Share of the recent values that fit into a column
+const COLUMN_PERCENTILE: f64 = 0.9;
+/// Maximum tag width of the compact layout
+const COMPACT_TAG_WIDTH: usize = 12;
+
+type Resize = Box<dyn Stream<Item = (), Error = Error> + Send>;
+
+/// Stream of SIGWINCH signals
+#[cfg(unix)]
+fn resize() -> Resize {
+    use futures::Future;
+    use tokio_signal::unix::{libc::SIGWINCH, Signal};
+
+    Box::new(
+        Signal::new(SIGWINCH)
+            .flatten_stream()
+            .map(|_| ())
+            .map_err(|e| format_err!("Failed to receive SIGWINCH: {}", e)),
+    )
+}
+
+#[cfg(not(unix))]
+fn resize() -> Resize {
+    Box::new(stream::empty())
+}
+
+/// Width of a column that fits a percentile of the recent values
+#[derive(Default)]
+struct Column {
+    recent: VecDeque<usize>,
+}
+
+impl Column {
+    /// Add the width of a value and return the column width up to `max`
+    fn push(&mut self, width: usize, max: usize) -> usize {
+        if self.recent.len() == COLUMN_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(width);
+        let mut recent = self.recent.iter().copied().collect::<Vec<_>>();
+        recent.sort_unstable();
+        let index = ((recent.len() - 1) as f64 * COLUMN_PERCENTILE).round() as usize;
+        min(recent[index], max)
+    }
+}
 
-const DIMM_COLOR: Color = Color::Ansi256(243);
+/// How messages longer than the terminal width are displayed
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Wrap {
+    /// Break at word boundaries
+    Word,
+    /// Break at any character
+    Char,
+    /// Show the first line truncated with an ellipsis
+    Truncate,
+}
 
-/// Construct a terminal sink for format from args with give profile
-pub fn try_from(args: &ArgMatches<'_>, profile: &Profile) -> Result<LogSink, Error> {
+/// Construct a terminal sink for format from args with given highlights
+pub fn try_from(args: &ArgMatches<'_>, highlight: Highlight) -> Result<LogSink, Error> {
     let format = args
         .value_of("format")
         .ok_or_else(|| format_err!("Missing format argument"))
         .and_then(|f| Format::from_str(f).map_err(err_msg))
         .unwrap_or(Format::Human);
 
-    if format == Format::Html {
-        return Err(format_err!("HTML format is only valid for file output"));
+    match format {
+        Format::Html => return Err(format_err!("HTML format is only valid for file output")),
+        Format::Sqlite => return Err(format_err!("SQLite format is only valid for file output")),
+        _ => (),
     }
 
     let sink = match format {
-        Format::Human => Box::new(Human::from(args, profile, format)) as LogSink,
+        Format::Human => {
+            let theme = theme::from_args(args)?;
+            Box::new(Human::from(args, highlight, theme)) as LogSink
+        }
         format => Box::new(FormatSink::new(format, stdout())) as LogSink,
     };
 
@@ -65,26 +133,30 @@ pub fn try_from(args: &ArgMatches<'_>, profile: &Profile) -> Result<LogSink, Err
 struct Human {
     writer: BufferWriter,
     date_format: Option<(&'static str, usize)>,
-    highlight: Vec<Regex>,
-    process_width: usize,
+    /// Show the elapsed time of records instead of the time of day
+    elapsed: bool,
+    wrap: Wrap,
+    highlight: Highlight,
+    process_width: Column,
     process_width_max: usize,
-    tag_width: Option<usize>,
-    thread_width: usize,
+    tag_width: Column,
+    /// Fixed tag width from the configuration
+    tag_width_fixed: Option<usize>,
+    thread_width: Column,
     thread_width_max: usize,
-    dimm_color: Option<Color>,
+    /// Omit process and thread and abbreviate tags
+    compact: bool,
+    /// Terminal width updated on resize
+    terminal_width: Option<usize>,
+    resize: Resize,
+    theme: Theme,
     bright_colors: bool,
     message_only: bool,
     process_filter: utils::ProcessFilter,
 }
 
 impl Human {
-    pub fn from(args: &ArgMatches<'_>, profile: &Profile, _: Format) -> Human {
-        let mut hl = profile.highlight.clone();
-        if args.is_present("highlight") {
-            hl.extend(values_t!(args.values_of("highlight"), String).unwrap());
-        }
-        let highlight = hl.iter().flat_map(|h| Regex::new(h)).collect();
-
+    pub fn from(args: &ArgMatches<'_>, highlight: Highlight, mut theme: Theme) -> Human {
         let color = {
             match args
                 .value_of("color")
@@ -93,7 +165,7 @@ impl Human {
                 "always" => ColorChoice::Always,
                 "never" => ColorChoice::Never,
                 "auto" => {
-                    if atty::is(atty::Stream::Stdout) {
+                    if atty::is(atty::Stream::Stdout) || pager::is_active() {
                         ColorChoice::Auto
                     } else {
                         ColorChoice::Never
@@ -103,7 +175,7 @@ impl Human {
             }
         };
         let no_dimm = args.is_present("no-dimm") || config_get("terminal_no_dimm").unwrap_or(false);
-        let tag_width = config_get("terminal_tag_width");
+        let tag_width_fixed = config_get("terminal_tag_width");
         let hide_timestamp = args.is_present("hide-timestamp")
             || config_get("terminal_hide_timestamp").unwrap_or(false);
         let show_date =
@@ -121,6 +193,16 @@ impl Human {
         };
         let message_only =
             args.is_present("message_only") || config_get("terminal_message_only").unwrap_or(false);
+        let wrap = match args
+            .value_of("wrap")
+            .map(ToOwned::to_owned)
+            .or_else(|| config_get("terminal_wrap"))
+            .as_deref()
+        {
+            Some("char") => Wrap::Char,
+            Some("truncate") => Wrap::Truncate,
+            _ => Wrap::Word,
+        };
 
         let bright_colors = args.is_present("bright_colors")
             || config_get("terminal_bright_colors").unwrap_or(false);
@@ -133,71 +215,78 @@ impl Human {
             packages.extend(values.map(String::from));
         }
 
-        let process_filter = utils::ProcessFilter::new(packages);
+        let process_filter =
+            utils::ProcessFilter::new(args.value_of("dev").map(ToOwned::to_owned), packages);
+
+        if no_dimm {
+            theme.dimm = None;
+        }
 
         // process_filter.packages.clear();
         Human {
             writer: BufferWriter::stdout(color),
-            dimm_color: if no_dimm { None } else { Some(DIMM_COLOR) },
+            theme,
             highlight,
             date_format,
-            tag_width,
-            process_width: 0,
+            elapsed: args.value_of("time").unwrap_or("absolute") != "absolute",
+            wrap,
+            tag_width: Column::default(),
+            tag_width_fixed,
+            process_width: Column::default(),
             process_width_max,
-            thread_width: 0,
+            thread_width: Column::default(),
             thread_width_max,
+            compact: args.is_present("compact") || config_get("terminal_compact").unwrap_or(false),
+            terminal_width: terminal_width(),
+            resize: resize(),
             bright_colors,
             message_only,
             process_filter,
         }
     }
 
-    // Dynamic tag width estimation according to terminal width
-    fn tag_width(&self) -> usize {
-        let terminal_width = terminal_width();
-        self.tag_width.unwrap_or(match terminal_width {
+    /// Tag width that fits most of the recent tags up to a maximum
+    /// according to the terminal width
+    fn tag_width(&mut self, tags: &[String]) -> usize {
+        if let Some(width) = self.tag_width_fixed {
+            return width;
+        }
+        let max = match self.terminal_width {
+            _ if self.compact => COMPACT_TAG_WIDTH,
             Some(n) if n <= 80 => 15,
             Some(n) if n <= 90 => 20,
             Some(n) if n <= 100 => 25,
             Some(n) if n <= 110 => 30,
             _ => 35,
-        })
+        };
+        let width = tags.iter().map(|t| str_width(t) + 1).sum::<usize>();
+        self.tag_width.push(width.saturating_sub(1), max)
     }
 
-    #[cfg(target_os = "windows")]
-    fn hashed_color(i: &str) -> Color {
-        let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
-        match v {
-            0 => Color::Blue,
-            1 => Color::Green,
-            2 => Color::Red,
-            3 => Color::Cyan,
-            4 => Color::Magenta,
-            5 => Color::Yellow,
-            _ => Color::White,
+    /// Update the terminal width after resize signals
+    fn poll_resize(&mut self) {
+        loop {
+            match self.resize.poll() {
+                Ok(Async::Ready(Some(()))) => self.terminal_width = terminal_width(),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(_) => {
+                    self.resize = Box::new(stream::empty());
+                    break;
+                }
+            }
         }
     }
 
-    #[cfg(not(target_os = "windows"))]
-    fn hashed_color(i: &str) -> Color {
-        // Some colors are hard to read on (at least) dark terminals
-        // and I consider some others as ugly.
-        Color::Ansi256(match i.bytes().fold(42u8, |c, x| c ^ x) {
-            c @ 0..=1 => c + 2,
-            c @ 16..=21 => c + 6,
-            c @ 52..=55 | c @ 126..=129 => c + 4,
-            c @ 163..=165 | c @ 200..=201 => c + 3,
-            c @ 207 => c + 1,
-            c @ 232..=240 => c + 9,
-            c => c,
-        })
-    }
-
     fn print(&mut self, mut record: Record) -> Result<(), Error> {
         if self.process_filter.should_skip_process(&record.process) {
             return Ok(());
         }
-        let timestamp = if let Some((format, len)) = self.date_format {
+        let timestamp = if let (Some(_), true) = (self.date_format, self.elapsed) {
+            match record.elapsed {
+                Some(elapsed) => format!("{elapsed:>11.3}s"),
+                None => " ".repeat(12),
+            }
+        } else if let Some((format, len)) = self.date_format {
             if let Some(ref ts) = record.timestamp {
                 let mut ts = time::strftime(format, ts).unwrap_or(" ".to_string());
                 ts.truncate(len);
@@ -209,73 +298,55 @@ impl Human {
             String::new()
         };
 
-        // Calculate colors before truncation
-        let process_color = Self::hashed_color(&record.process);
-        let thread_color = Self::hashed_color(&record.thread);
-
-        /// Truncate `s` to width characters, adding "…" if necessary
-        fn format_trim(s: &mut String, width: usize) {
-            let len = s.chars().count();
-            if len > width {
-                s.truncate(width);
-                s.pop();
-                s.push('…');
-            }
+        let gap = record.gap;
 
-            if len < width {
-                s.reserve(width - len);
-                for _ in 0..(width - len) {
-                    s.push(' ')
-                }
-            }
-        }
+        // Calculate colors before truncation
+        let process_style = self.theme.process(&record.process);
+        let thread_style = self.theme.thread(&record.thread);
 
         // Tag
-        let tag_width = self.tag_width();
+        let tag_width = self.tag_width(&record.tags);
 
         // Process
-        self.process_width = min(
-            max(self.process_width, record.process.chars().count()),
-            self.process_width_max,
-        );
-        format_trim(&mut record.process, self.process_width);
+        let process_width = self
+            .process_width
+            .push(str_width(&record.process), self.process_width_max);
+        format_trim(&mut record.process, process_width);
 
         // Thread
-        self.thread_width = min(
-            max(self.thread_width, record.thread.chars().count()),
-            self.thread_width_max,
-        );
-        format_trim(&mut record.thread, self.thread_width);
-
-        let highlight = !self.highlight.is_empty()
-            && (self
-                .highlight
-                .iter()
-                .any(|r| record.tags.iter().any(|t| r.is_match(t)))
-                || self.highlight.iter().any(|r| r.is_match(&record.message)));
+        let thread_width = self
+            .thread_width
+            .push(str_width(&record.thread), self.thread_width_max);
+        format_trim(&mut record.thread, thread_width);
+
+        let rules = self.highlight.lock().expect("Failed to lock highlight");
+        let highlighted = rules.iter().any(|r| {
+            record.tags.iter().any(|t| r.pattern.is_match(t)) || r.pattern.is_match(&record.message)
+        });
 
         let do_preamble = !self.message_only;
 
-        let preamble_width = if do_preamble {
+        let preamble_width = if !do_preamble {
+            0
+        } else if self.compact {
+            timestamp.chars().count()
+                + 1 // " "
+                + tag_width
+                + 1 // " "
+                + 3 // level
+        } else {
             timestamp.chars().count()
                 + 1 // " "
                 + tag_width
                 + 2 // " ("
-                + self.process_width
+                + process_width
                 + 1
-                + self.thread_width
+                + thread_width
                 + 2 // ") "
                 + 3 // level
-        } else {
-            0
         };
 
-        let level_color = match record.level {
-            Level::Info => Some(Color::Green),
-            Level::Warn => Some(Color::Yellow),
-            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
-            _ => self.dimm_color,
-        };
+        let level_style = self.theme.level(&record.level, record.output.as_ref());
 
         let Record {
             message,
@@ -286,12 +357,19 @@ impl Human {
             ..
         } = record;
 
+        let compact = self.compact;
+
         // Collect as many tags as needed
         let mut tags: Vec<_> = {
             let tags = tags.into_iter().map(|t| {
-                let chars = t.chars().count();
-                let color = Self::hashed_color(&t);
-                (t, chars, Some(color))
+                let t = if compact {
+                    abbreviate(&t, tag_width)
+                } else {
+                    t
+                };
+                let width = str_width(&t);
+                let style = self.theme.tag(&t);
+                (t, width, style)
             });
 
             // Add spaces between the tags
@@ -301,18 +379,18 @@ impl Human {
             let space_left = &mut space_left;
             let mut tags = tags
                 .into_iter()
-                .map_while(|(mut tag, chars, color)| {
+                .map_while(|(mut tag, width, style)| {
                     if *space_left == 0 {
                         return None;
                     }
 
                     let left = *space_left;
-                    *space_left = space_left.saturating_sub(chars);
+                    *space_left = space_left.saturating_sub(width);
 
                     if *space_left == 0 {
                         format_trim(&mut tag, left);
                     }
-                    Some((tag, color))
+                    Some((tag, style))
                 })
                 .collect::<Vec<_>>();
 
@@ -323,66 +401,86 @@ impl Human {
         };
 
         let mut preamble = |buffer: &mut Buffer| -> Result<(), Error> {
-            let mut spec = ColorSpec::new();
-
-            let timestamp_color = if highlight {
-                Some(Color::Yellow)
+            let timestamp_style = if gap {
+                Some(self.theme.gap)
+            } else if highlighted {
+                Some(self.theme.highlight)
             } else {
-                self.dimm_color
+                self.theme.dimm
             };
 
             // Timestamp
-            buffer.set_color(spec.set_fg(timestamp_color))?;
+            buffer.set_color(&spec(timestamp_style))?;
             buffer.write_all(timestamp.as_bytes())?;
 
             // Tags
             buffer.write_all(b" ")?;
-            for (tag, color) in &mut tags {
-                buffer.set_color(spec.set_fg(*color))?;
+            for (tag, style) in &mut tags {
+                buffer.set_color(&spec(*style))?;
                 buffer.write_all(tag.as_bytes())?;
             }
 
-            // Process and thread
-            buffer.set_color(spec.set_fg(None))?;
-            buffer.write_all(b" (")?;
-            buffer.set_color(spec.set_fg(Some(process_color)))?;
-            buffer.write_all(process.as_bytes())?;
-            if !thread.is_empty() {
-                buffer.set_color(spec.set_fg(Some(thread_color)))?;
+            if compact {
+                buffer.set_color(&ColorSpec::new())?;
                 buffer.write_all(b" ")?;
-                buffer.write_all(thread.as_bytes())?;
+            } else {
+                // Process and thread
+                buffer.set_color(&ColorSpec::new())?;
+                buffer.write_all(b" (")?;
+                buffer.set_color(&spec(process_style))?;
+                buffer.write_all(process.as_bytes())?;
+                if !thread.is_empty() {
+                    buffer.set_color(&spec(thread_style))?;
+                    buffer.write_all(b" ")?;
+                    buffer.write_all(thread.as_bytes())?;
+                }
+                buffer.set_color(&ColorSpec::new())?;
+                buffer.write_all(b") ")?;
             }
-            buffer.set_color(spec.set_fg(None))?;
-            buffer.write_all(b") ")?;
 
             // Level
-            buffer.set_color(
-                spec.set_bg(level_color)
-                    .set_fg(level_color.map(|_| Color::Black)),
-            )?;
+            let mut level_spec = ColorSpec::new();
+            match level_style {
+                Some(Style { fg: Some(fg), .. }) => {
+                    level_spec.set_bg(Some(fg)).set_fg(self.theme.level_fg)
+                }
+                Some(style) => level_spec.set_bold(style.bold),
+                None => &mut level_spec,
+            };
+            buffer.set_color(&level_spec)?;
             write!(buffer, " {} ", level)?;
             buffer.set_color(&ColorSpec::new())?;
 
             Ok(())
         };
 
-        let payload_len = terminal_width().unwrap_or(usize::MAX) - preamble_width - 3;
+        let payload_len = self
+            .terminal_width
+            .map(|w| w.saturating_sub(preamble_width + 3).max(MIN_PAYLOAD_WIDTH))
+            .unwrap_or(usize::MAX);
         let message = message.replace('\t', "<TAB>");
-        let message_len = message.chars().count();
-        let chunks = message_len / payload_len + 1;
+        let styles = highlight::styles(&rules, &message);
+        let chars = message.chars().zip(styles).collect::<Vec<_>>();
+        let (rows, truncated) = match self.wrap {
+            Wrap::Truncate => {
+                let (row, truncated) = truncate(&message, payload_len);
+                (vec![row], truncated)
+            }
+            mode => (wrap(&message, payload_len, mode), false),
+        };
 
         let mut buffer = self.writer.buffer();
 
-        for i in 0..chunks {
+        for (i, row) in rows.iter().enumerate() {
             if do_preamble {
                 preamble(&mut buffer)?;
             }
 
-            let c = if chunks == 1 {
+            let c = if rows.len() == 1 {
                 "   "
             } else if i == 0 {
                 " ┌ "
-            } else if i == chunks - 1 {
+            } else if i == rows.len() - 1 {
                 " └ "
             } else {
                 " ├ "
@@ -390,17 +488,22 @@ impl Human {
 
             buffer.write_all(c.as_bytes())?;
 
-            let chunk = message
-                .chars()
-                .skip(i * payload_len)
-                .take(payload_len)
-                .collect::<String>();
-            buffer.set_color(
-                ColorSpec::new()
-                    .set_intense(self.bright_colors)
-                    .set_fg(level_color),
-            )?;
-            buffer.write_all(chunk.as_bytes())?;
+            let mut row = chars[row.clone()].to_vec();
+            if truncated {
+                row.push(('…', None));
+            }
+            // Write runs of characters with the same highlight style
+            for run in row.chunk_by(|a, b| a.1 == b.1) {
+                let mut spec = spec(level_style);
+                spec.set_intense(self.bright_colors);
+                if let Some(style) = run[0].1 {
+                    style.apply(&mut spec);
+                }
+                buffer.set_color(&spec)?;
+                let run = run.iter().map(|(c, _)| *c).collect::<String>();
+                buffer.write_all(run.as_bytes())?;
+            }
+            buffer.reset()?;
             buffer.write_all(b"\n")?;
         }
 
@@ -408,6 +511,175 @@ impl Human {
     }
 }
 
+/// Truncate `s` to width characters, adding "…" if necessary
+fn format_trim(s: &mut String, width: usize) {
+    if str_width(s) > width {
+        let mut len = 0;
+        *s = s
+            .chars()
+            .take_while(|c| {
+                len += char_width(*c);
+                len < width
+            })
+            .collect();
+        if width > 0 {
+            s.push('…');
+        }
+    }
+
+    let len = str_width(s);
+    if len < width {
+        s.extend((0..width - len).map(|_| ' '));
+    }
+}
+
+/// Shorten `tag` to `width` characters by abbreviating all words but the
+/// last e.g `ActivityManager` to `ActManager`
+fn abbreviate(tag: &str, width: usize) -> String {
+    if str_width(tag) <= width {
+        return tag.to_owned();
+    }
+
+    // Split at camel case and separators
+    let mut words: Vec<String> = Vec::new();
+    let mut boundary = true;
+    let mut previous = ' ';
+    for c in tag.chars() {
+        if !c.is_alphanumeric() {
+            boundary = true;
+            continue;
+        }
+        if boundary || (c.is_uppercase() && previous.is_lowercase()) {
+            words.push(String::new());
+        }
+        if let Some(word) = words.last_mut() {
+            word.push(c);
+        }
+        boundary = false;
+        previous = c;
+    }
+
+    if let Some((last, words)) = words.split_last() {
+        let longest = words.iter().map(|w| w.chars().count()).max().unwrap_or(0);
+        for n in (1..longest).rev() {
+            let abbreviation = words
+                .iter()
+                .flat_map(|w| w.chars().take(n))
+                .chain(last.chars())
+                .collect::<String>();
+            if str_width(&abbreviation) <= width {
+                return abbreviation;
+            }
+        }
+    }
+
+    let mut tag = tag.to_owned();
+    format_trim(&mut tag, width);
+    tag
+}
+
+/// Display width of `c` in terminal columns
+fn char_width(c: char) -> usize {
+    UnicodeWidthChar::width(c).unwrap_or(0)
+}
+
+/// Display width of `s` in terminal columns
+fn str_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// Split `text` into rows of at most `width` columns. Returns the char
+/// ranges of the rows. Newlines always start a new row.
+fn wrap(text: &str, width: usize, mode: Wrap) -> Vec<Range<usize>> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut rows = Vec::new();
+    let mut start = 0;
+    for line in chars.split(|c| *c == '\n') {
+        let end = start + line.len();
+        let first = rows.len();
+        let mut row = start;
+        let mut columns = 0;
+        // Start of the current word
+        let mut word = None;
+        for (i, c) in line.iter().enumerate().map(|(i, c)| (start + i, c)) {
+            let w = char_width(*c);
+            if columns + w > width && i > row {
+                // Drop the whitespace at a word break
+                if mode == Wrap::Word && c.is_whitespace() {
+                    rows.push(row..i);
+                    row = i + 1;
+                    columns = 0;
+                    word = None;
+                    continue;
+                }
+                let next = match word {
+                    Some(word) if mode == Wrap::Word && word > row => word,
+                    _ => i,
+                };
+                let mut trimmed = next;
+                while mode == Wrap::Word && trimmed > row && chars[trimmed - 1].is_whitespace() {
+                    trimmed -= 1;
+                }
+                rows.push(row..trimmed);
+                row = next;
+                columns = chars[row..i].iter().map(|c| char_width(*c)).sum();
+            }
+            columns += w;
+            if c.is_whitespace() {
+                word = None;
+            } else if word.is_none() || i == row {
+                word = Some(i);
+            }
+        }
+        if row < end || rows.len() == first {
+            rows.push(row..end);
+        }
+        // Skip the newline
+        start = end + 1;
+    }
+    // Carriage returns of \r\n line endings
+    for row in &mut rows {
+        if row.end > row.start && chars[row.end - 1] == '\r' {
+            row.end -= 1;
+        }
+    }
+    rows
+}
+
+/// First line of `text` truncated to `width` columns including an ellipsis.
+/// Returns the char range of the row and whether it is truncated.
+fn truncate(text: &str, width: usize) -> (Range<usize>, bool) {
+    let line = text.split('\n').next().unwrap_or_default();
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let more = line.len() < text.trim_end().len();
+    let mut columns = 0;
+    let mut end = 0;
+    for (i, c) in line.chars().enumerate() {
+        columns += char_width(c);
+        if columns > width {
+            break;
+        }
+        end = i + 1;
+    }
+    if end == line.chars().count() && !more {
+        return (0..end, false);
+    }
+    // Make room for the ellipsis
+    while end > 0 && line.chars().take(end).map(char_width).sum::<usize>() + 1 > width {
+        end -= 1;
+    }
+    (0..end, true)
+}
+
+/// Color spec of `style` or the default spec
+fn spec(style: Option<Style>) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    if let Some(style) = style {
+        style.apply(&mut spec);
+    }
+    spec
+}
+
 impl Drop for Human {
     fn drop(&mut self) {
         let mut buffer = self.writer.buffer();
@@ -451,6 +723,7 @@ impl Sink for Human {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.poll_resize();
         self.print(record).map(|_| AsyncSink::Ready)
     }
 
@@ -458,3 +731,76 @@ impl Sink for Human {
         Ok(Async::Ready(()))
     }
 }
+
+#[test]
+fn test_wrap() {
+    let rows = |text: &str, width, mode| {
+        let chars = text.chars().collect::<Vec<_>>();
+        wrap(text, width, mode)
+            .into_iter()
+            .map(|r| chars[r].iter().collect::<String>())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        rows("hello brave new world", 11, Wrap::Word),
+        vec!["hello brave", "new world"]
+    );
+    assert_eq!(
+        rows("hello brave new world", 11, Wrap::Char),
+        vec!["hello brave", " new world"]
+    );
+    assert_eq!(
+        rows("a verylongword", 6, Wrap::Word),
+        vec!["a", "verylo", "ngword"]
+    );
+    // Double width characters
+    assert_eq!(
+        rows("日本語の文", 4, Wrap::Char),
+        vec!["日本", "語の", "文"]
+    );
+    assert_eq!(
+        rows("first\r\n\nsecond line", 8, Wrap::Word),
+        vec!["first", "", "second", "line"]
+    );
+    assert_eq!(rows("", 8, Wrap::Word), vec![""]);
+    assert_eq!(rows("abc", 3, Wrap::Word), vec!["abc"]);
+
+    assert_eq!(truncate("short", 10), (0..5, false));
+    assert_eq!(truncate("a longer message", 8), (0..7, true));
+    assert_eq!(truncate("line\nmore", 10), (0..4, true));
+    assert_eq!(truncate("日本語", 4), (0..1, true));
+}
+
+#[test]
+fn test_columns() {
+    let mut column = Column::default();
+    assert_eq!(column.push(5, 20), 5);
+    for _ in 0..20 {
+        column.push(8, 20);
+    }
+    // A single long value does not widen the column
+    assert_eq!(column.push(40, 20), 8);
+    for _ in 0..COLUMN_WINDOW {
+        column.push(40, 20);
+    }
+    assert_eq!(column.push(3, 20), 20);
+
+    assert_eq!(abbreviate("ActivityManager", 20), "ActivityManager");
+    assert_eq!(abbreviate("ActivityManager", 10), "ActManager");
+    assert_eq!(abbreviate("ActivityManager", 8), "AManager");
+    assert_eq!(abbreviate("chromium_net_http", 8), "chnehttp");
+    assert_eq!(abbreviate("ActivityManager", 5), "Acti…");
+    assert_eq!(abbreviate("Zygöte", 4), "Zyg…");
+
+    let trim = |s: &str, width| {
+        let mut s = s.to_owned();
+        format_trim(&mut s, width);
+        s
+    };
+    assert_eq!(trim("日本語", 2), "… ");
+    assert_eq!(trim("日本語", 3), "日…");
+    assert_eq!(trim("日本語", 6), "日本語");
+    assert_eq!(trim("日本語", 7), "日本語 ");
+    assert_eq!(trim("abc", 2), "a…");
+    assert_eq!(abbreviate("日本語", 4), "日… ");
+}
@@ -0,0 +1,401 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{highlight::Style, utils::config_try_get};
+use clap::ArgMatches;
+use crc::crc32;
+use failure::{format_err, Error};
+use rogcat::record::{Level, Output};
+use serde::Deserialize;
+use std::{collections::HashMap, env, str::FromStr};
+use termcolor::Color;
+
+/// Names of the builtin themes
+pub const THEMES: &[&str] = &["dark", "light", "solarized", "mono"];
+
+/// Accent colors of solarized as rgb and closest 256 color
+const SOLARIZED: [((u8, u8, u8), u8); 8] = [
+    ((0xb5, 0x89, 0x00), 136),
+    ((0xcb, 0x4b, 0x16), 166),
+    ((0xdc, 0x32, 0x2f), 160),
+    ((0xd3, 0x36, 0x82), 125),
+    ((0x6c, 0x71, 0xc4), 61),
+    ((0x26, 0x8b, 0xd2), 33),
+    ((0x2a, 0xa1, 0x98), 37),
+    ((0x85, 0x99, 0x00), 64),
+];
+
+/// 256 colors that are readable on light backgrounds
+const LIGHT: [u8; 24] = [
+    18, 19, 20, 22, 23, 24, 25, 26, 28, 29, 30, 31, 52, 53, 54, 55, 88, 89, 90, 91, 94, 124, 127,
+    130,
+];
+
+/// Colors assigned to tags, processes and threads
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Palette {
+    Dark,
+    Light,
+    Solarized,
+    None,
+}
+
+/// Colors of the human terminal output
+#[derive(Clone, Debug)]
+pub struct Theme {
+    levels: Vec<(Level, Style)>,
+    /// Unparsed stderr output of commands
+    stderr: Style,
+    /// Timestamps and records without level color
+    pub dimm: Option<Style>,
+    /// Timestamp of records with highlights
+    pub highlight: Style,
+    /// Timestamp of records after a gap
+    pub gap: Style,
+    /// Foreground of the level column
+    pub level_fg: Option<Color>,
+    tag: Option<Style>,
+    process: Option<Style>,
+    thread: Option<Style>,
+    /// Pinned tag colors
+    tags: HashMap<String, Style>,
+    palette: Palette,
+    truecolor: bool,
+}
+
+/// Layout of the `[theme]` section of the configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeConfig {
+    name: Option<String>,
+    truecolor: Option<bool>,
+    timestamp: Option<String>,
+    gap: Option<String>,
+    tag: Option<String>,
+    process: Option<String>,
+    thread: Option<String>,
+    level: HashMap<String, String>,
+    tags: HashMap<String, String>,
+}
+
+fn fg(color: Color) -> Style {
+    Style {
+        fg: Some(color),
+        ..Default::default()
+    }
+}
+
+impl Theme {
+    /// Builtin theme `name`
+    pub fn named(name: &str, truecolor: bool) -> Result<Theme, Error> {
+        let levels = |info, warn, error| {
+            vec![
+                (Level::Info, info),
+                (Level::Warn, warn),
+                (Level::Error, error),
+                (Level::Fatal, error),
+                (Level::Assert, error),
+            ]
+        };
+        let bold = |color| Style {
+            fg: color,
+            bold: true,
+            ..Default::default()
+        };
+        let theme = |levels, stderr, dimm, highlight, gap, level_fg, palette| Theme {
+            levels,
+            stderr,
+            dimm: Some(dimm),
+            highlight,
+            gap,
+            level_fg,
+            tag: None,
+            process: None,
+            thread: None,
+            tags: HashMap::new(),
+            palette,
+            truecolor,
+        };
+        Ok(match name {
+            "dark" => theme(
+                levels(fg(Color::Green), fg(Color::Yellow), fg(Color::Red)),
+                fg(Color::Magenta),
+                fg(Color::Ansi256(243)),
+                fg(Color::Yellow),
+                bold(Some(Color::Red)),
+                Some(Color::Black),
+                Palette::Dark,
+            ),
+            "light" => theme(
+                levels(
+                    fg(Color::Ansi256(28)),
+                    fg(Color::Ansi256(130)),
+                    fg(Color::Ansi256(160)),
+                ),
+                fg(Color::Ansi256(90)),
+                fg(Color::Ansi256(244)),
+                fg(Color::Ansi256(166)),
+                bold(Some(Color::Ansi256(160))),
+                Some(Color::White),
+                Palette::Light,
+            ),
+            "solarized" => {
+                let accent = |i: usize| {
+                    let ((r, g, b), c) = SOLARIZED[i];
+                    fg(if truecolor {
+                        Color::Rgb(r, g, b)
+                    } else {
+                        Color::Ansi256(c)
+                    })
+                };
+                let base = if truecolor {
+                    Color::Rgb(0x83, 0x94, 0x96)
+                } else {
+                    Color::Ansi256(245)
+                };
+                theme(
+                    levels(accent(7), accent(0), accent(2)),
+                    accent(3),
+                    fg(base),
+                    accent(1),
+                    bold(accent(2).fg),
+                    Some(Color::Black),
+                    Palette::Solarized,
+                )
+            }
+            "mono" => theme(
+                levels(Style::default(), Style::default(), bold(None)),
+                Style::default(),
+                Style::default(),
+                bold(None),
+                Style {
+                    underline: true,
+                    ..bold(None)
+                },
+                None,
+                Palette::None,
+            ),
+            name => {
+                return Err(format_err!(
+                    "Unknown theme \"{}\". Choose one of {}",
+                    name,
+                    THEMES.join(", ")
+                ))
+            }
+        })
+    }
+
+    /// Style of the level column and message of records with `level`
+    pub fn level(&self, level: &Level, output: Option<&Output>) -> Option<Style> {
+        match self.levels.iter().find(|(l, _)| l == level) {
+            Some((_, style)) => Some(*style),
+            None if output == Some(&Output::Stderr) => Some(self.stderr),
+            None => self.dimm,
+        }
+    }
+
+    /// Color of `tag`. Pinned tags take precedence.
+    pub fn tag(&self, tag: &str) -> Option<Style> {
+        self.tags
+            .get(tag)
+            .copied()
+            .or(self.tag)
+            .or_else(|| self.hashed(tag))
+    }
+
+    pub fn process(&self, process: &str) -> Option<Style> {
+        self.process.or_else(|| self.hashed(process))
+    }
+
+    pub fn thread(&self, thread: &str) -> Option<Style> {
+        self.thread.or_else(|| self.hashed(thread))
+    }
+
+    /// Stable color of `value` from the palette
+    fn hashed(&self, value: &str) -> Option<Style> {
+        let hash = crc32::checksum_ieee(value.as_bytes());
+        let color = match (self.palette, self.truecolor) {
+            (Palette::None, _) => return None,
+            (Palette::Dark, false) => ansi256(value),
+            (Palette::Light, false) => Color::Ansi256(LIGHT[hash as usize % LIGHT.len()]),
+            (Palette::Solarized, false) => Color::Ansi256(SOLARIZED[hash as usize % 8].1),
+            (Palette::Solarized, true) => {
+                let (r, g, b) = SOLARIZED[hash as usize % 8].0;
+                Color::Rgb(r, g, b)
+            }
+            (Palette::Dark, true) => hsl(hash % 360, 0.6, 0.65),
+            (Palette::Light, true) => hsl(hash % 360, 0.7, 0.35),
+        };
+        Some(fg(color))
+    }
+
+    /// Apply the `[theme]` section of the configuration file
+    fn configure(&mut self, config: ThemeConfig) -> Result<(), Error> {
+        let style = |s: &str| {
+            Style::from_str(s).map_err(|e| format_err!("Invalid theme configuration: {}", e))
+        };
+        if let Some(ref timestamp) = config.timestamp {
+            self.dimm = Some(style(timestamp)?);
+        }
+        if let Some(ref gap) = config.gap {
+            self.gap = style(gap)?;
+        }
+        if let Some(ref tag) = config.tag {
+            self.tag = Some(style(tag)?);
+        }
+        if let Some(ref process) = config.process {
+            self.process = Some(style(process)?);
+        }
+        if let Some(ref thread) = config.thread {
+            self.thread = Some(style(thread)?);
+        }
+        for (level, s) in &config.level {
+            let s = style(s)?;
+            if level == "stderr" {
+                self.stderr = s;
+                continue;
+            }
+            let level = match Level::from(level.as_str()) {
+                Level::None => {
+                    return Err(format_err!(
+                        "Invalid theme configuration: unknown level \"{}\"",
+                        level
+                    ))
+                }
+                level => level,
+            };
+            match self.levels.iter_mut().find(|(l, _)| *l == level) {
+                Some((_, style)) => *style = s,
+                None => self.levels.push((level, s)),
+            }
+        }
+        for (tag, s) in &config.tags {
+            self.tags.insert(tag.clone(), style(s)?);
+        }
+        Ok(())
+    }
+}
+
+/// Theme from the `--theme` option and the configuration file
+pub fn from_args(args: &ArgMatches) -> Result<Theme, Error> {
+    let config = config_try_get::<ThemeConfig>("theme")
+        .map_err(|e| format_err!("Invalid theme configuration: {}", e))?
+        .unwrap_or_default();
+    let name = args
+        .value_of("theme")
+        .map(ToOwned::to_owned)
+        .or_else(|| config.name.clone())
+        .unwrap_or_else(|| "dark".to_owned());
+    let truecolor = config.truecolor.unwrap_or_else(|| {
+        env::var("COLORTERM")
+            .map(|c| c == "truecolor" || c == "24bit")
+            .unwrap_or(false)
+    });
+    let mut theme = Theme::named(&name, truecolor)?;
+    theme.configure(config)?;
+    Ok(theme)
+}
+
+#[cfg(target_os = "windows")]
+fn ansi256(i: &str) -> Color {
+    let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
+    match v {
+        0 => Color::Blue,
+        1 => Color::Green,
+        2 => Color::Red,
+        3 => Color::Cyan,
+        4 => Color::Magenta,
+        5 => Color::Yellow,
+        _ => Color::White,
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn ansi256(i: &str) -> Color {
+    // Some colors are hard to read on (at least) dark terminals
+    // and I consider some others as ugly.
+    Color::Ansi256(match i.bytes().fold(42u8, |c, x| c ^ x) {
+        c @ 0..=1 => c + 2,
+        c @ 16..=21 => c + 6,
+        c @ 52..=55 | c @ 126..=129 => c + 4,
+        c @ 163..=165 | c @ 200..=201 => c + 3,
+        c @ 207 => c + 1,
+        c @ 232..=240 => c + 9,
+        c => c,
+    })
+}
+
+/// Rgb color of `hue` in degrees, `saturation` and `lightness`
+fn hsl(hue: u32, saturation: f64, lightness: f64) -> Color {
+    let c = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let h = f64::from(hue) / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = lightness - c / 2.0;
+    let v = |v: f64| ((v + m) * 255.0).round() as u8;
+    Color::Rgb(v(r), v(g), v(b))
+}
+
+#[test]
+fn test_theme() {
+    let mut theme = Theme::named("dark", false).unwrap();
+    assert!(Theme::named("pink", false).is_err());
+    assert_eq!(theme.level(&Level::Info, None), Some(fg(Color::Green)));
+    assert_eq!(
+        theme.level(&Level::None, Some(&Output::Stderr)),
+        Some(fg(Color::Magenta))
+    );
+    assert_eq!(theme.tag("Foo"), theme.tag("Foo"));
+
+    let config = ThemeConfig {
+        tag: Some("cyan".into()),
+        level: vec![("info".to_owned(), "bold blue".to_owned())]
+            .into_iter()
+            .collect(),
+        tags: vec![("ActivityManager".to_owned(), "blue".to_owned())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    theme.configure(config).unwrap();
+    assert_eq!(theme.tag("ActivityManager"), Some(fg(Color::Blue)));
+    assert_eq!(theme.tag("Foo"), Some(fg(Color::Cyan)));
+    assert!(theme.level(&Level::Info, None).unwrap().bold);
+
+    let config = ThemeConfig {
+        level: vec![("loud".to_owned(), "red".to_owned())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    assert!(theme.configure(config).is_err());
+
+    let theme = Theme::named("mono", true).unwrap();
+    assert_eq!(theme.tag("Foo"), None);
+    assert_eq!(hsl(0, 1.0, 0.5), Color::Rgb(255, 0, 0));
+    assert_eq!(hsl(240, 1.0, 0.5), Color::Rgb(0, 0, 255));
+}
@@ -0,0 +1,416 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    filter::{self, Filter},
+    profiles::Profile,
+    reader, subcommands,
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{try_ready, Async, Poll, Stream};
+use rogcat::record::Record;
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Names of the actions
+const ACTIONS: &[&str] = &["exec", "bugreport", "marker", "exit", "count"];
+
+/// What to do when a trigger matches
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Run a shell command with the record in the environment
+    Exec(String),
+    /// Capture a bugreport into a directory
+    Bugreport(PathBuf),
+    /// Insert a marker record after the matching record
+    Marker(String),
+    /// Stop and exit with code
+    Exit(i32),
+    /// Stop and exit with code once the trigger matched more than n times
+    Count(usize, i32),
+}
+
+/// Filter and action e.g `-t ActivityManager -m ANR => bugreport`
+#[derive(Debug)]
+pub struct Trigger {
+    spec: String,
+    filter: Option<Filter>,
+    action: Action,
+    matches: usize,
+    /// Matches skipped while the command of an exec action runs. None if idle
+    running: Arc<Mutex<Option<usize>>>,
+}
+
+impl Trigger {
+    /// Parse a trigger of the form `FILTER => ACTION`. Like in highlights only
+    /// a `=>` surrounded by whitespace separates the action. The last one that
+    /// is followed by an action wins so that both the filter and an `exec`
+    /// command may contain `=>`.
+    pub fn parse(spec: &str) -> Result<Trigger, Error> {
+        let separators = spec
+            .rmatch_indices("=>")
+            .map(|(i, _)| i)
+            .filter(|&i| {
+                (i == 0 || spec[..i].ends_with(char::is_whitespace))
+                    && spec[i + 2..].starts_with(char::is_whitespace)
+            })
+            .collect::<Vec<_>>();
+        let separator = separators
+            .iter()
+            .find(|&&i| {
+                let action = spec[i + 2..].split_whitespace().next();
+                action.is_some_and(|a| ACTIONS.contains(&a))
+            })
+            .or_else(|| separators.first())
+            .ok_or_else(|| format_err!("Invalid trigger \"{}\": missing =>", spec))?;
+        let (filter, action) = (&spec[..*separator], &spec[separator + 2..]);
+        let filter = filter::parse(filter.trim())
+            .map_err(|e| format_err!("Invalid trigger \"{}\": {}", spec, e))?;
+
+        let action = action.trim();
+        let (name, argument) = action
+            .split_once(char::is_whitespace)
+            .map(|(n, a)| (n, a.trim()))
+            .unwrap_or((action, ""));
+        let code = |s: &str| {
+            s.parse::<i32>()
+                .map_err(|e| format_err!("Invalid trigger \"{}\": {}", spec, e))
+        };
+        let action = match (name, argument) {
+            ("exec", "") | ("marker", "") | ("count", "") => {
+                return Err(format_err!(
+                    "Invalid trigger \"{}\": {} needs an argument",
+                    spec,
+                    name
+                ))
+            }
+            ("exec", command) => Action::Exec(command.to_owned()),
+            ("bugreport", "") => Action::Bugreport(PathBuf::from(".")),
+            ("bugreport", dir) => Action::Bugreport(PathBuf::from(dir)),
+            ("marker", text) => Action::Marker(text.to_owned()),
+            ("exit", "") => Action::Exit(1),
+            ("exit", c) => Action::Exit(code(c)?),
+            ("count", arguments) => {
+                let mut arguments = arguments.split_whitespace();
+                let limit = arguments
+                    .next()
+                    .unwrap_or_default()
+                    .parse::<usize>()
+                    .map_err(|e| format_err!("Invalid trigger \"{}\": {}", spec, e))?;
+                let code = arguments.next().map(code).transpose()?.unwrap_or(1);
+                Action::Count(limit, code)
+            }
+            (name, _) => {
+                return Err(format_err!(
+                    "Invalid trigger \"{}\": unknown action {}",
+                    spec,
+                    name
+                ))
+            }
+        };
+
+        Ok(Trigger {
+            spec: spec.to_owned(),
+            filter,
+            action,
+            matches: 0,
+            running: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.filter
+            .as_ref()
+            .map(|f| f.filter(record))
+            .unwrap_or(true)
+    }
+}
+
+/// Triggers from args and profile
+pub fn from_args_profile(args: &ArgMatches, profile: &Profile) -> Result<Vec<Trigger>, Error> {
+    args.values_of("trigger")
+        .unwrap_or_default()
+        .chain(profile.trigger.iter().map(String::as_str))
+        .map(Trigger::parse)
+        .collect()
+}
+
+/// Run `command` with the fields of `record` in the environment. `running`
+/// is reset once the command finished.
+fn exec(command: &str, record: &Record, running: Arc<Mutex<Option<usize>>>) -> Result<(), Error> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    let timestamp = record
+        .timestamp
+        .as_ref()
+        .and_then(|t| time::strftime("%m-%d %H:%M:%S.%f", t).ok())
+        .unwrap_or_default();
+    let mut child = cmd
+        .arg(command)
+        .env("ROGCAT_TIMESTAMP", timestamp)
+        .env("ROGCAT_LEVEL", record.level.to_string())
+        .env("ROGCAT_TAG", record.tags.join(","))
+        .env("ROGCAT_PROCESS", &record.process)
+        .env("ROGCAT_THREAD", &record.thread)
+        .env("ROGCAT_MESSAGE", &record.message)
+        .env("ROGCAT_RAW", &record.raw)
+        .spawn()
+        .map_err(|e| format_err!("Failed to run \"{}\": {}", command, e))?;
+    let command = command.to_owned();
+    thread::spawn(move || {
+        child.wait().ok();
+        let skipped = running.lock().expect("Failed to lock trigger").take();
+        if let Some(skipped) = skipped.filter(|s| *s > 0) {
+            eprintln!("Skipped {skipped} matches while \"{command}\" was running");
+        }
+    });
+    Ok(())
+}
+
+/// Evaluate triggers on all records, filter the records and insert markers.
+/// The stream ends after an exit trigger matched.
+pub struct Triggers<S> {
+    inner: S,
+    filter: Arc<Mutex<Filter>>,
+    triggers: Vec<Trigger>,
+    serial: Option<String>,
+    /// Set while a bugreport is captured
+    bugreport: Arc<AtomicBool>,
+    queue: VecDeque<Record>,
+    exit: Arc<Mutex<Option<i32>>>,
+}
+
+impl<S> Triggers<S> {
+    pub fn new(
+        inner: S,
+        filter: Filter,
+        triggers: Vec<Trigger>,
+        serial: Option<String>,
+    ) -> Triggers<S> {
+        Triggers {
+            inner,
+            filter: Arc::new(Mutex::new(filter)),
+            triggers,
+            serial,
+            bugreport: Arc::new(AtomicBool::new(false)),
+            queue: VecDeque::new(),
+            exit: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Filter that can be replaced while the stream is running
+    pub fn shared_filter(&self) -> Arc<Mutex<Filter>> {
+        self.filter.clone()
+    }
+
+    /// Exit code set by an exit or count trigger
+    pub fn exit_code(&self) -> Arc<Mutex<Option<i32>>> {
+        self.exit.clone()
+    }
+
+    fn process(&mut self, record: Record) {
+        let mut markers = Vec::new();
+        let mut exit = None;
+        for trigger in self.triggers.iter_mut().filter(|t| t.matches(&record)) {
+            trigger.matches += 1;
+            match trigger.action {
+                Action::Exec(ref command) => {
+                    let mut running = trigger.running.lock().expect("Failed to lock trigger");
+                    if let Some(skipped) = running.as_mut() {
+                        *skipped += 1;
+                        continue;
+                    }
+                    match exec(command, &record, trigger.running.clone()) {
+                        Ok(()) => *running = Some(0),
+                        Err(e) => eprintln!("{e}"),
+                    }
+                }
+                Action::Bugreport(ref dir) => {
+                    if self.bugreport.swap(true, Ordering::SeqCst) {
+                        eprintln!(
+                            "Skipping bugreport of \"{}\": capture in progress",
+                            trigger.spec
+                        );
+                        continue;
+                    }
+                    let (dir, serial, busy) =
+                        (dir.clone(), self.serial.clone(), self.bugreport.clone());
+                    thread::spawn(move || {
+                        match subcommands::capture_bugreport(serial.as_deref(), &dir) {
+                            Ok(filename) => eprintln!("Captured {}", filename.display()),
+                            Err(e) => eprintln!("Failed to capture bugreport: {e}"),
+                        }
+                        busy.store(false, Ordering::SeqCst);
+                    });
+                }
+                Action::Marker(ref text) => markers.push(reader::marker(text)),
+                Action::Exit(code) => {
+                    eprintln!("Trigger \"{}\" matched", trigger.spec);
+                    exit = exit.or(Some(code));
+                }
+                Action::Count(limit, code) => {
+                    if trigger.matches > limit {
+                        eprintln!(
+                            "Trigger \"{}\" matched more than {} times",
+                            trigger.spec, limit
+                        );
+                        exit = exit.or(Some(code));
+                    }
+                }
+            }
+        }
+
+        if self
+            .filter
+            .lock()
+            .expect("Failed to lock filter")
+            .filter(&record)
+        {
+            self.queue.push_back(record);
+        }
+        self.queue.extend(markers);
+        if exit.is_some() {
+            *self.exit.lock().expect("Failed to lock exit code") = exit;
+        }
+    }
+}
+
+impl<S> Stream for Triggers<S>
+where
+    S: Stream<Item = Record, Error = Error>,
+{
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(record) = self.queue.pop_front() {
+                return Ok(Async::Ready(Some(record)));
+            }
+            if self
+                .exit
+                .lock()
+                .expect("Failed to lock exit code")
+                .is_some()
+            {
+                return Ok(Async::Ready(None));
+            }
+            match try_ready!(self.inner.poll()) {
+                Some(record) => self.process(record),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let action = |spec: &str| Trigger::parse(spec).map(|t| t.action);
+    assert_eq!(
+        action("-m ANR => exec echo \"$ROGCAT_MESSAGE\"").unwrap(),
+        Action::Exec("echo \"$ROGCAT_MESSAGE\"".into())
+    );
+    assert_eq!(
+        action("=> bugreport").unwrap(),
+        Action::Bugreport(PathBuf::from("."))
+    );
+    assert_eq!(
+        action("-l E => marker error seen").unwrap(),
+        Action::Marker("error seen".into())
+    );
+    assert_eq!(action("-t Foo => exit 3").unwrap(), Action::Exit(3));
+    assert_eq!(action("-l E => count 10").unwrap(), Action::Count(10, 1));
+    assert_eq!(action("-l E => count 10 2").unwrap(), Action::Count(10, 2));
+    assert!(action("-l E").is_err());
+    assert!(action("-l E => count").is_err());
+    assert!(action("-l E => reboot").is_err());
+    assert!(action("--foo => exit").is_err());
+    assert_eq!(action("-m a=>b => exit 2").unwrap(), Action::Exit(2));
+    assert_eq!(action("-m \"a => b\" => exit 2").unwrap(), Action::Exit(2));
+    assert_eq!(
+        action("-m a => exec echo a => b").unwrap(),
+        Action::Exec("echo a => b".into())
+    );
+    assert!(action("-m a=>exit").is_err());
+}
+
+#[test]
+fn test_triggers() {
+    use futures::stream::iter_ok;
+
+    let record = |tag: &str| Record {
+        tags: vec![tag.to_owned()],
+        ..Default::default()
+    };
+    let triggers = ["-t B => marker b", "-t C => count 1 4"]
+        .iter()
+        .map(|t| Trigger::parse(t).unwrap())
+        .collect();
+    let filter = filter::parse("-t !A").unwrap().unwrap();
+    let records = ["A", "B", "C", "C", "D"]
+        .iter()
+        .map(|t| record(t))
+        .collect::<Vec<_>>();
+    let stream = Triggers::new(iter_ok::<_, Error>(records), filter, triggers, None);
+    let exit = stream.exit_code();
+
+    let messages = stream
+        .wait()
+        .map(|r| {
+            let r = r.unwrap();
+            r.tags.first().cloned().unwrap_or(r.message)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(messages, vec!["B", "--- b ---", "C", "C"]);
+    assert_eq!(*exit.lock().unwrap(), Some(4));
+}
+
+#[test]
+fn test_exec() {
+    use futures::stream::empty;
+
+    let record = |tag: &str| Record {
+        tags: vec![tag.to_owned()],
+        ..Default::default()
+    };
+    let triggers = vec![Trigger::parse("-t B => exec sleep 1").unwrap()];
+    let filter = filter::parse("-t B").unwrap().unwrap();
+    let mut stream = Triggers::new(empty::<Record, Error>(), filter, triggers, None);
+    stream.process(record("B"));
+    stream.process(record("A"));
+    stream.process(record("B"));
+    assert_eq!(*stream.triggers[0].running.lock().unwrap(), Some(1));
+}
@@ -18,20 +18,27 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::adb;
 use config::{Config, File};
-use failure::Error;
+use failure::{format_err, Error};
 use lazy_static::lazy_static;
 use serde::Deserialize;
 use std::{convert::Into, env, path::PathBuf, sync::RwLock};
 use which::which_in;
 
 lazy_static! {
-    static ref CONFIG: RwLock<Config> = RwLock::new({
-        Config::builder()
-            .add_source(File::from(config_dir().join("config.toml")).required(false))
-            .build()
-            .unwrap_or_default()
-    });
+    static ref CONFIG: RwLock<Config> = RwLock::new(load_config().unwrap_or_default());
+}
+
+/// Path of the configuration file
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+fn load_config() -> Result<Config, config::ConfigError> {
+    Config::builder()
+        .add_source(File::from(config_file()).required(false))
+        .build()
 }
 
 /// Find adb binary
@@ -39,6 +46,51 @@ pub fn adb() -> Result<PathBuf, Error> {
     which_in("adb", env::var_os("PATH"), env::current_dir()?).map_err(Into::into)
 }
 
+/// Create an adb command with the device selector if present
+pub fn adb_command(serial: Option<&str>) -> Result<std::process::Command, Error> {
+    let mut command = std::process::Command::new(adb()?);
+    if let Some(serial) = serial {
+        command.arg("-s").arg(serial);
+    }
+    Ok(command)
+}
+
+/// Run a shell command on the device and return stdout. The command is
+/// executed via the adb server and falls back to the adb binary.
+pub fn shell(serial: Option<&str>, args: &[&str]) -> Result<String, Error> {
+    let command = args
+        .iter()
+        .map(|a| adb::quote(a))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if let Ok(output) = adb::Client::default().shell(serial, &command) {
+        return Ok(String::from_utf8_lossy(&output).into_owned());
+    }
+
+    let output = adb_command(serial)?.arg("shell").arg(&command).output()?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Query the SDK version of the device
+pub fn sdk_version(serial: Option<&str>) -> Result<u32, Error> {
+    parse_sdk_version(&shell(serial, &["getprop", "ro.build.version.sdk"])?)
+}
+
+/// Parse the output of `getprop ro.build.version.sdk`
+fn parse_sdk_version(output: &str) -> Result<u32, Error> {
+    output
+        .trim()
+        .parse()
+        .map_err(|e| format_err!("Invalid SDK version \"{}\": {}", output.trim(), e))
+}
+
 pub fn terminal_width() -> Option<usize> {
     match term_size::dimensions() {
         Some((width, _)) => Some(width),
@@ -62,23 +114,41 @@ pub fn config_get<'a, T: Deserialize<'a>>(key: &'a str) -> Option<T> {
     CONFIG.read().ok().and_then(|c| c.get::<T>(key).ok())
 }
 
+/// Read a value from the configuration file. Returns None if the key is
+/// missing and an error if the value is invalid.
+pub fn config_try_get<'a, T: Deserialize<'a>>(key: &'a str) -> Result<Option<T>, Error> {
+    let config = CONFIG.read().expect("Failed to get config lock");
+    match config.get::<T>(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub fn config_init() {
     drop(CONFIG.read().expect("Failed to get config lock"));
 }
 
-pub fn get_pids(packages: &[String]) -> Result<std::collections::HashSet<u32>, Error> {
+/// Read the configuration file again. The current configuration is kept if
+/// the file is invalid.
+pub fn config_reload() -> Result<(), Error> {
+    let config = load_config()
+        .map_err(|e| format_err!("Failed to read {}: {}", config_file().display(), e))?;
+    *CONFIG.write().expect("Failed to get config lock") = config;
+    Ok(())
+}
+
+pub fn get_pids(
+    serial: Option<&str>,
+    packages: &[String],
+) -> Result<std::collections::HashSet<u32>, Error> {
     if packages.is_empty() {
         return Ok(std::collections::HashSet::new());
     }
 
-    let mut command = std::process::Command::new(adb()?);
-    command.arg("shell").arg("pidof");
-    for pkg in packages {
-        command.arg(pkg);
-    }
-
-    let output = command.stdout(std::process::Stdio::piped()).output()?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut args = vec!["pidof"];
+    args.extend(packages.iter().map(String::as_str));
+    let stdout = shell(serial, &args)?;
     let mut pids = std::collections::HashSet::new();
 
     for word in stdout.split_whitespace() {
@@ -91,6 +161,7 @@ pub fn get_pids(packages: &[String]) -> Result<std::collections::HashSet<u32>, E
 }
 
 pub struct ProcessFilter {
+    serial: Option<String>,
     packages: Vec<String>,
     valid_pids: std::collections::HashSet<u32>,
     last_update: std::time::Instant,
@@ -99,13 +170,14 @@ pub struct ProcessFilter {
 impl ProcessFilter {
     const TTL: std::time::Duration = std::time::Duration::from_secs(2);
 
-    pub fn new(packages: Vec<String>) -> Self {
+    pub fn new(serial: Option<String>, packages: Vec<String>) -> Self {
         // Initialize with a past timestamp so it updates immediately on first use
         let last_update = std::time::Instant::now()
             .checked_sub(Self::TTL + std::time::Duration::from_secs(1))
             .unwrap_or_else(std::time::Instant::now);
 
         Self {
+            serial,
             packages,
             valid_pids: std::collections::HashSet::new(),
             last_update,
@@ -124,7 +196,7 @@ impl ProcessFilter {
 
         let now = std::time::Instant::now();
         if now.duration_since(self.last_update) > Self::TTL {
-            if let Ok(pids) = get_pids(&self.packages) {
+            if let Ok(pids) = get_pids(self.serial.as_deref(), &self.packages) {
                 self.valid_pids = pids;
             }
             self.last_update = now;
@@ -133,3 +205,36 @@ impl ProcessFilter {
         !self.valid_pids.contains(&pid)
     }
 }
+
+/// Directory for test files that is removed when dropped, also if the test fails
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("rogcat-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).expect("Failed to create temporary directory");
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+#[test]
+fn test_sdk_version() {
+    assert_eq!(parse_sdk_version("29\r\n").unwrap(), 29);
+    assert_eq!(parse_sdk_version("23").unwrap(), 23);
+    assert!(parse_sdk_version("").is_err());
+    assert!(parse_sdk_version("/system/bin/sh: getprop: not found").is_err());
+}